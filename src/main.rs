@@ -1,15 +1,95 @@
 use ggez::{Context, GameResult};
 use ggez::graphics::{self, Color, DrawParam, Mesh, Text};
 use ggez::event::{self, EventHandler};
-use ggez::input::{keyboard::{KeyCode, KeyInput}, mouse::MouseButton};
+use ggez::input::{keyboard::{KeyCode, KeyInput, KeyMods}, mouse::MouseButton};
 use ggez::mint::Point2;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::f32::consts::PI;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 const WINDOW_WIDTH: f32 = 1600.0;
 const WINDOW_HEIGHT: f32 = 1200.0;
+const WINDOW_CENTER: Point2<f32> = Point2 { x: WINDOW_WIDTH / 2.0, y: WINDOW_HEIGHT / 2.0 };
 const G: f32 = 1.0;
 const DT: f32 = 0.016;
+const MERGE_EFFECT_LIFETIME: u32 = 30; // frames
+const FADE_IN_STEPS: u32 = 90;
+const DEFAULT_PARTICLE_CSV: &str = "particles.csv";
+const DEFAULT_STATS_CSV: &str = "stats.csv";
+const DEFAULT_SWEEP_CSV: &str = "sweep.csv";
+const SWEEP_HEADER: &str = "parameter,value,seed,steps,energy_drift_percent,merges,max_mass,count";
+const SWEEP_DEFAULT_STEPS: u64 = 1000;
+const STATS_HEADER: &str = "step,time,kinetic,potential,total_energy,momentum_x,momentum_y,momentum_z,angular_momentum_z,count,max_mass";
+const BOOKMARKS_FILE: &str = "camera_bookmarks.csv";
+const BOOKMARK_SLOTS: usize = 9;
+const DEFAULT_FRAMES_DIR: &str = "/frames"; // inside ggez's user data directory
+const CONTOUR_CELL_SIZE: f32 = 32.0; // screen pixels per potential grid cell
+const CONTOUR_LEVELS: usize = 12;
+const MASS_LABEL_THRESHOLD: f32 = 20.0; // skip labels on dust lighter than this
+const HISTORY_CAPACITY: usize = 256;
+const COLLAPSE_MIN_SURVIVORS: usize = 3; // fewer orbiting bodies than this counts as a collapse
+const COLLAPSE_VELOCITY_BOOST: f32 = 1.5;
+const TIMING_SMOOTHING: f32 = 0.05; // weight of the newest frame in the timing averages
+const BINARY_STAR_SEPARATION: f32 = 40.0;
+const CENTRAL_STAR_FRACTION: f32 = 0.5; // of the heaviest mass, to count as one of the central stars
+const STAR_DOMINANCE_THRESHOLD: f32 = 0.8; // share of the pull that must come from the star to draw an ellipse
+const ORBIT_ELLIPSE_SEGMENTS: usize = 96;
+const PREDICTION_STEPS: usize = 600;
+const TRACER_DRAW_RADIUS: f32 = 1.5; // screen pixels, independent of zoom
+const MAX_FRAME_SKIP: u32 = 16;
+const DEFAULT_ZOOM_STEP: f32 = 1.1;
+const FOCUSING_MAX: f32 = 4.0; // cap on the focusing boost, so near-zero approach speeds stay sane
+const KM_S_PER_AU_YR: f32 = 4.740_47; // 1 AU/yr in km/s
+const GUN_SOFTENING: f32 = 20.0; // world units; keeps the cursor pull finite up close
+const VIEW_ROTATION_STEP: f32 = PI / 12.0; // 15° per [ or ] press
+const HILL_MIN_MASS: f32 = 20.0; // lighter bodies get no Hill sphere, to avoid clutter
+const SPIN_INDICATOR_MIN: f32 = 0.05; // radians per time unit before a spin is drawn
+const SIZE_SOFTENING_FACTOR: f32 = 0.5; // smoothing length per unit of mean pair radius
+const UNIT_CIRCLE_TOLERANCE_SCALE: f32 = 0.02; // unit particle mesh is scaled up to ~50 px
+const UI_STRIP_HEIGHT: f32 = 50.0; // button row across the top of the window
+const UI_PANEL_WIDTH: f32 = 490.0; // slider column, through the text boxes
+const SMOOTH_TICK: f32 = 1.0 / 20.0; // seconds between physics ticks in smooth-motion mode
+const STABLE_STEP_FRACTION: f32 = 0.5; // of the close-pass timescale, see likely_unstable
+const TRAIL_LENGTH: usize = 300; // steps of history kept per tracked particle
+const PHASE_WINDOW: u64 = 200; // steps of merges behind each phase label
+const LEADER_PATH_LENGTH: usize = 5000; // steps of the heaviest body's path kept
+const MASS_LIST_WIDTH: f32 = 280.0;
+const MASS_LIST_ROW_HEIGHT: f32 = 20.0;
+const MASS_LIST_ROWS: usize = 16;
+const MASS_LIST_REFRESH: u32 = 30; // frames between re-sorts
+const DENSITY_BINS: usize = 24;
+const DENSITY_BIN_WIDTH: f32 = 20.0; // world units per annulus
+const DENSITY_REFRESH: u32 = 15; // frames between profile updates
+const FLOW_CELL: f32 = 40.0; // world units per side of a velocity field cell
+const FLOW_REFRESH: u32 = 10; // frames between velocity field updates
+const CAMERA_FLIGHT_DURATION: f32 = 0.6; // seconds
+const REST_FRAME_ARROWS: usize = 24; // nearest bodies given relative-velocity arrows
+const REST_FRAME_LOOKAHEAD: f32 = 1.0; // arrow length, in sim time of relative motion
+const FIXED_PARTICLE_RADIUS: f32 = 4.0;
+const GRID_COLLISION_THRESHOLD: usize = 64; // below this, all-pairs collision checks are cheaper
+const BINARY_SCAN_INTERVAL: u64 = 30; // steps between bound-pair searches
+const BINARY_MAX_SEPARATION: f32 = 40.0;
+const CLUSTER_LINKING_LENGTH: f32 = 20.0; // friends-of-friends reach, in world units
+const CLUSTER_MIN_MEMBERS: usize = 3;
+const TILT_FORESHORTENING: f32 = 0.45; // vertical squash of the tilted disk
+const TILT_PERSPECTIVE: f32 = 0.35; // how much smaller the far side is drawn
+const NUDGE_POSITION_STEP: f32 = 1.0;
+const NUDGE_VELOCITY_STEP: f32 = 0.1;
+const FOCUS_RING_MIN: f32 = 3.0; // ring gap beyond the particle's edge, in pixels
+const FOCUS_RING_MAX: f32 = 8.0;
+const FOCUS_RING_PERIOD: f32 = 1.2; // seconds per pulse
+const FOCUS_RING_INTRO: f32 = 0.25; // seconds for the ring to close in after selecting
+const STARFIELD_SEED: u64 = 0x5eed_57a2;
+const SHAKE_SEED: u64 = 0x5a4e_0001;
+const STARFIELD_COUNT: usize = 400;
+const STARFIELD_PARALLAX: f32 = 0.2; // background moves at this fraction of the pan
+const LENSING_MIN_MASS: f32 = 100.0; // lighter bodies don't distort the background
+const LENSING_SCALE: f32 = 0.5; // Einstein radius in pixels per sqrt(mass × zoom)
 
 #[derive(Clone, Copy)]
 struct Vector3<T> {
@@ -46,6 +126,16 @@ impl Point3<f32> {
         }
     }
 }
+const UI_FONT_SIZE: f32 = 16.0;
+const UI_SCALE_STEP: f32 = 0.25;
+
+// Text at the UI font size times the UI scale
+fn ui_text(text: impl Into<String>, scale: f32) -> Text {
+    let mut text = Text::new(text.into());
+    text.set_scale(UI_FONT_SIZE * scale);
+    text
+}
+
 struct Button {
     rect: graphics::Rect,
     text: String,
@@ -61,23 +151,29 @@ impl Button {
         }
     }
 
-    fn contains(&self, point: Point2<f32>) -> bool {
-        self.rect.contains(point)
+    // Layout rectangle with the UI scale applied
+    fn scaled_rect(&self, scale: f32) -> graphics::Rect {
+        graphics::Rect::new(self.rect.x * scale, self.rect.y * scale, self.rect.w * scale, self.rect.h * scale)
+    }
+
+    fn contains(&self, point: Point2<f32>, scale: f32) -> bool {
+        self.scaled_rect(scale).contains(point)
     }
 
-    fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+    fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, scale: f32) -> GameResult {
+        let bounds = self.scaled_rect(scale);
         let rect = Mesh::new_rectangle(
             ctx,
             graphics::DrawMode::fill(),
-            self.rect,
+            bounds,
             if self.clicked { Color::BLUE } else { Color::from_rgb(100, 100, 100) },
         )?;
         canvas.draw(&rect, DrawParam::default());
         
-        let text = Text::new(&self.text);
+        let text = ui_text(&self.text, scale);
         let text_pos = Point2 {
-            x: self.rect.x + 10.0,
-            y: self.rect.y + 5.0,
+            x: bounds.x + 10.0 * scale,
+            y: bounds.y + 5.0 * scale,
         };
         canvas.draw(&text, DrawParam::default().dest(text_pos).color(Color::WHITE));
         Ok(())
@@ -91,6 +187,8 @@ struct Slider {
     label: String,
     y_pos: f32,
     text_input: Option<String>,
+    // Values snap to multiples of this (offset from min) when set
+    step: Option<f32>,
 }
 
 impl Slider {
@@ -102,37 +200,65 @@ impl Slider {
             label: label.to_string(),
             y_pos,
             text_input: if text_input { Some(String::new()) } else { None },
+            step: None,
+        }
+    }
+
+    fn with_step(mut self, step: f32) -> Self {
+        self.step = Some(step);
+        self.value = self.snap(self.value);
+        self
+    }
+
+    fn snap(&self, value: f32) -> f32 {
+        match self.step {
+            Some(step) => (self.min + ((value - self.min) / step).round() * step).clamp(self.min, self.max),
+            None => value,
         }
     }
 
-    fn handle_click(&mut self, x: f32, y: f32) -> bool {
-        if y >= self.y_pos && y <= self.y_pos + 20.0 && x >= 150.0 && x <= 350.0 {
-            self.value = self.min + (self.max - self.min) * ((x - 150.0) / 200.0);
+    // Sets the value from a horizontal track position, clamped to the track
+    fn set_from_x(&mut self, x: f32, scale: f32) {
+        let fraction = ((x / scale - 150.0) / 200.0).clamp(0.0, 1.0);
+        self.value = self.snap(self.min + (self.max - self.min) * fraction);
+    }
+
+    // Whether (x, y) falls in the typed-value box, for sliders that have one
+    fn input_contains(&self, x: f32, y: f32, scale: f32) -> bool {
+        let (local_x, local_y) = (x / scale, y / scale);
+        self.text_input.is_some() && local_y >= self.y_pos && local_y <= self.y_pos + 20.0 && (420.0..=480.0).contains(&local_x)
+    }
+
+    fn handle_click(&mut self, x: f32, y: f32, scale: f32) -> bool {
+        let (local_x, local_y) = (x / scale, y / scale);
+        if local_y >= self.y_pos && local_y <= self.y_pos + 20.0 && (150.0..=350.0).contains(&local_x) {
+            self.set_from_x(x, scale);
             true
         } else {
             false
         }
     }
 
-    fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
-        let text = Text::new(&self.label);
-        canvas.draw(&text, DrawParam::default().dest([10.0, self.y_pos]).color(Color::WHITE));
+    fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, scale: f32, tolerance: f32) -> GameResult {
+        let y_pos = self.y_pos * scale;
+        let text = ui_text(&self.label, scale);
+        canvas.draw(&text, DrawParam::default().dest([10.0 * scale, y_pos]).color(Color::WHITE));
 
         let slider_bg = Mesh::new_rectangle(
             ctx,
             graphics::DrawMode::fill(),
-            graphics::Rect::new(150.0, self.y_pos, 200.0, 20.0),
+            graphics::Rect::new(150.0 * scale, y_pos, 200.0 * scale, 20.0 * scale),
             Color::from_rgb(50, 50, 50),
         )?;
         canvas.draw(&slider_bg, DrawParam::default());
 
-        let position = 150.0 + 200.0 * (self.value - self.min) / (self.max - self.min);
+        let position = (150.0 + 200.0 * (self.value - self.min) / (self.max - self.min)) * scale;
         let slider_handle = Mesh::new_circle(
             ctx,
             graphics::DrawMode::fill(),
-            Point2 { x: position, y: self.y_pos + 10.0 },
-            10.0,
-            0.1,
+            Point2 { x: position, y: y_pos + 10.0 * scale },
+            10.0 * scale,
+            tolerance,
             Color::WHITE,
         )?;
         canvas.draw(&slider_handle, DrawParam::default());
@@ -140,29 +266,232 @@ impl Slider {
         // Display value
         let value_text = if self.value >= 1000.0 {
             format!("{:.1e}", self.value)
+        } else if self.step.is_some_and(|step| step.fract() == 0.0) {
+            format!("{:.0}", self.value)
         } else {
             format!("{:.2}", self.value)
         };
-        let value_display = Text::new(&value_text);
-        canvas.draw(&value_display, DrawParam::default().dest([360.0, self.y_pos]).color(Color::WHITE));
+        let value_display = ui_text(value_text, scale);
+        canvas.draw(&value_display, DrawParam::default().dest([360.0 * scale, y_pos]).color(Color::WHITE));
 
-        // Text input for particle count
+        // Text input for typed values
         if let Some(text_input) = &self.text_input {
             let input_bg = Mesh::new_rectangle(
                 ctx,
                 graphics::DrawMode::fill(),
-                graphics::Rect::new(420.0, self.y_pos, 60.0, 20.0),
+                graphics::Rect::new(420.0 * scale, y_pos, 60.0 * scale, 20.0 * scale),
                 Color::from_rgb(30, 30, 30),
             )?;
             canvas.draw(&input_bg, DrawParam::default());
-            let input_text = Text::new(text_input);
-            canvas.draw(&input_text, DrawParam::default().dest([425.0, self.y_pos]).color(Color::WHITE));
+            let input_text = ui_text(text_input, scale);
+            canvas.draw(&input_text, DrawParam::default().dest([425.0 * scale, y_pos]).color(Color::WHITE));
         }
 
         Ok(())
     }
 }
 
+// Knobs of the force law, gathered so new ones don't widen every signature
+#[derive(Clone, Copy)]
+struct ForceSettings {
+    is_3d: bool,
+    // When false every particle drifts in a straight line
+    gravity: bool,
+    // force ∝ 1/dist^exponent; 2.0 is Newtonian gravity
+    exponent: f32,
+    // Linear gas drag, acceleration = -drag * velocity; 0.0 disables it
+    drag: f32,
+    // Cap on each particle's total acceleration; 0.0 leaves it uncapped
+    max_acceleration: f32,
+    // Pairs further apart than this are ignored; 0.0 means no cutoff
+    cutoff: f32,
+    // Global softening length from the Softening slider; its square is added
+    // to every squared distance so close passes don't blow up
+    softening: f32,
+    // Soften each pair by the bodies' sizes (pair_softening) instead of the global term
+    size_softening: bool,
+}
+
+// The button strip always sits over the world; `panel` optionally adds the
+// area behind the sliders
+fn ui_blocks_point(point: Point2<f32>, scale: f32, panel: Option<graphics::Rect>) -> bool {
+    point.y <= UI_STRIP_HEIGHT * scale || panel.is_some_and(|panel| panel.contains(point))
+}
+
+// Radius within which a body of `mass` orbiting `star_mass` at `distance`
+// holds on to material against the star's tide
+fn hill_radius(mass: f32, star_mass: f32, distance: f32) -> f32 {
+    distance * (mass / (3.0 * star_mass)).cbrt()
+}
+
+// Speed needed to escape a point mass from `distance` away
+fn escape_velocity(mass: f32, distance: f32) -> f32 {
+    (2.0 * G * mass / distance.max(f32::EPSILON)).sqrt()
+}
+
+// The body pulling hardest on a test mass at `point` (x/y plane), with its distance
+fn strongest_pull_at(particles: &[Particle], point: Point2<f32>) -> Option<(usize, f32)> {
+    particles
+        .iter()
+        .enumerate()
+        .filter(|(_, particle)| !particle.tracer)
+        .map(|(i, particle)| {
+            let (dx, dy) = (particle.position.x - point.x, particle.position.y - point.y);
+            let distance_sq = (dx * dx + dy * dy).max(f32::EPSILON);
+            (i, distance_sq.sqrt(), particle.mass / distance_sq)
+        })
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(i, distance, _)| (i, distance))
+}
+
+// Zoom multiplier for a wheel movement of `delta`: one `step` per notch in
+// either direction, or with `smooth` scaled by how far the wheel moved
+fn zoom_factor(delta: f32, step: f32, smooth: bool) -> f32 {
+    if smooth {
+        step.powf(delta)
+    } else if delta > 0.0 {
+        step
+    } else if delta < 0.0 {
+        1.0 / step
+    } else {
+        1.0
+    }
+}
+
+// Whether a particle of `age` steps has outlived `lifetime`; 0 means forever
+fn has_expired(age: u32, lifetime: u32) -> bool {
+    lifetime > 0 && age >= lifetime
+}
+
+// True on exactly the step a run should stop at; a target of 0 never stops
+fn at_step_target(steps: u64, target: u64) -> bool {
+    target > 0 && steps == target
+}
+
+// The direct-sum force law in f64: accelerations of every body at
+// `positions`, with velocities for the drag term
+fn accelerations_f64(particles: &[Particle], positions: &[[f64; 3]], velocities: &[[f64; 3]], settings: &ForceSettings) -> Vec<[f64; 3]> {
+    let global_softening = (settings.softening * settings.softening) as f64;
+    let drag = settings.drag as f64;
+    let exponent = settings.exponent as f64;
+    let cutoff = settings.cutoff as f64;
+    (0..particles.len())
+        .map(|i| {
+            let mut acceleration = velocities[i].map(|v| -drag * v);
+            if !settings.gravity {
+                return acceleration;
+            }
+            for (j, other) in particles.iter().enumerate() {
+                if i == j || other.tracer {
+                    continue;
+                }
+                let d = [0, 1, 2].map(|k| positions[j][k] - positions[i][k]);
+                let raw_squared = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+                if cutoff > 0.0 && raw_squared > cutoff * cutoff {
+                    continue;
+                }
+                let epsilon = if settings.size_softening {
+                    pair_softening(particles[i].radius, other.radius) as f64
+                } else {
+                    global_softening
+                };
+                let dist_squared = raw_squared + epsilon;
+                let dist = dist_squared.sqrt();
+                if dist < (particles[i].radius + other.radius) as f64 {
+                    continue;
+                }
+                let force = G as f64 * other.mass as f64 / dist_squared.powf(exponent / 2.0);
+                acceleration[0] += force * d[0] / dist;
+                acceleration[1] += force * d[1] / dist;
+                if settings.is_3d {
+                    acceleration[2] += force * d[2] / dist;
+                }
+            }
+            let max = settings.max_acceleration as f64;
+            let length = acceleration.iter().map(|a| a * a).sum::<f64>().sqrt();
+            if max > 0.0 && length > max {
+                acceleration = acceleration.map(|a| a * max / length);
+            }
+            acceleration
+        })
+        .collect()
+}
+
+// One kick-drift-kick step done entirely in f64, always summing every pair.
+// The f32 fields are rounded from the result for rendering and the rest of
+// the simulation.
+fn step_f64(particles: &mut [Particle], dt: f64, settings: &ForceSettings) {
+    let states: Vec<PreciseState> = particles.iter().map(Particle::precise_state).collect();
+    let mut positions: Vec<[f64; 3]> = states.iter().map(|state| state.0).collect();
+    let mut velocities: Vec<[f64; 3]> = states.iter().map(|state| state.1).collect();
+    let before = accelerations_f64(particles, &positions, &velocities, settings);
+    for (i, particle) in particles.iter().enumerate() {
+        if particle.fixed {
+            velocities[i] = [0.0; 3];
+            continue;
+        }
+        for k in 0..3 {
+            velocities[i][k] += before[i][k] * dt * 0.5;
+            positions[i][k] += velocities[i][k] * dt;
+        }
+    }
+    let after = accelerations_f64(particles, &positions, &velocities, settings);
+    for (i, particle) in particles.iter_mut().enumerate() {
+        if !particle.fixed {
+            for k in 0..3 {
+                velocities[i][k] += after[i][k] * dt * 0.5;
+            }
+        }
+        let ([x, y, z], [vx, vy, vz], [ax, ay, az]) = (positions[i], velocities[i], after[i]);
+        particle.position = Point3 { x: x as f32, y: y as f32, z: z as f32 };
+        particle.velocity = Vector3 { x: vx as f32, y: vy as f32, z: vz as f32 };
+        particle.acceleration = Vector3 { x: ax as f32, y: ay as f32, z: az as f32 };
+        particle.precise = Some((positions[i], velocities[i]));
+    }
+}
+
+// Squared smoothing length for a pair when softening follows size: a fixed
+// fraction of their mean radius, so big bodies are smoothed more than dust
+fn pair_softening(radius_a: f32, radius_b: f32) -> f32 {
+    let length = SIZE_SOFTENING_FACTOR * (radius_a + radius_b) / 2.0;
+    length * length
+}
+
+// Rough stability check for the integrator: a particle skimming the heaviest
+// body at the softening length orbits on a timescale of sqrt(eps^3 / (G M)),
+// and the step has to be a small fraction of that to follow it
+fn likely_unstable(softening: f32, dt: f32, max_mass: f32) -> bool {
+    if max_mass <= 0.0 {
+        return false;
+    }
+    let length = softening.max(f32::EPSILON);
+    let timescale = (length * length * length / (G * max_mass)).sqrt();
+    dt > STABLE_STEP_FRACTION * timescale
+}
+
+// Draw opacity of a particle that was added mid-run, rising from faint to
+// fully opaque over FADE_IN_STEPS. It never starts fully transparent so a
+// mass placed while paused is still visible.
+fn fade_in_alpha(age: u32) -> f32 {
+    0.25 + 0.75 * (age as f32 / FADE_IN_STEPS as f32).min(1.0)
+}
+
+// Nearest power of two to dt (e.g. 1/64). Sums of these are exact in
+// floating point, so long runs accumulate time reproducibly.
+fn snap_to_power_of_two(dt: f32) -> f32 {
+    2.0f32.powi(dt.log2().round() as i32)
+}
+
+// Magnitude of the pull (as acceleration) from `mass` at the given squared
+// distance under a 1/dist^exponent law
+fn force_magnitude(mass: f32, dist_squared: f32, exponent: f32) -> f32 {
+    if exponent == 2.0 {
+        G * mass / dist_squared
+    } else {
+        G * mass / dist_squared.powf(exponent / 2.0)
+    }
+}
+
 #[derive(Clone)]
 struct Particle {
     position: Point3<f32>,
@@ -173,50 +502,251 @@ struct Particle {
     // acceleration: Point2<f32>,
     mass: f32,
     radius: f32,
+    // Steps since the particle was created
+    age: u32,
+    // Assigned from the spawn radius and kept for life, for mixing studies
+    birth_color: Color,
+    // Anchors still pull on everything else but are never integrated
+    fixed: bool,
+    // Massless markers that feel gravity but exert none and never collide
+    tracer: bool,
+    // Recent positions, kept only for particles the user is tracking
+    trail: Option<VecDeque<Point3<f32>>>,
+    // Where the particle was before the last physics tick, for smooth motion
+    prev_position: Point3<f32>,
+    // Angular velocity about the z axis, picked up from off-center merges
+    spin: f32,
+    // Placed by hand with Add Mass; Soft Reset keeps these
+    user_placed: bool,
+    // One of the central stars a reset or preset started with; these never expire
+    central: bool,
+    // Full-precision position and velocity while the physics runs in f64
+    precise: Option<PreciseState>,
+    // Unique per created particle and kept through clones and merges (the
+    // absorbing body keeps its own), so a body can be followed as indices shift
+    id: u64,
+}
+
+static NEXT_PARTICLE_ID: AtomicU64 = AtomicU64::new(0);
+
+// Motion-blur streak: from, to, width and color on screen
+type Streak = (Point2<f32>, Point2<f32>, f32, Color);
+
+// Position and velocity in f64, [x, y, z] each
+type PreciseState = ([f64; 3], [f64; 3]);
+
+// Moment of inertia of a uniform ball, for turning spin into angular momentum
+fn spin_inertia(mass: f32, radius: f32) -> f32 {
+    0.4 * mass * radius * radius
+}
+
+// z angular momentum about the world origin, orbital plus spin
+fn total_angular_momentum_z(particles: &[Particle]) -> f32 {
+    angular_momentum_z(particles) + particles.iter().map(|p| spin_inertia(p.mass, p.radius) * p.spin).sum::<f32>()
+}
+
+// How a particle's radius follows from its mass
+#[derive(Clone, Copy, PartialEq)]
+enum RadiusModel {
+    ConstantDensity,
+    // Every particle gets this radius regardless of mass
+    Fixed(f32),
+}
+
+impl RadiusModel {
+    fn radius(self, mass: f32) -> f32 {
+        match self {
+            RadiusModel::ConstantDensity => mass.powf(0.3).max(2.0),
+            RadiusModel::Fixed(radius) => radius,
+        }
+    }
 }
 
 impl Particle {
+    fn radius_for_mass(mass: f32) -> f32 {
+        RadiusModel::ConstantDensity.radius(mass)
+    }
+
     fn new(x: f32, y: f32, z: f32, mass: f32) -> Self {
         Particle {
             position: Point3 { x, y, z },
             velocity: Vector3 { x: 0.0, y: 0.0, z: 0.0 },
             acceleration: Vector3 { x: 0.0, y: 0.0, z: 0.0 },
             mass,
-            radius: mass.powf(0.3).max(2.0),
+            radius: Particle::radius_for_mass(mass),
+            age: 0,
+            birth_color: Color::WHITE,
+            fixed: false,
+            tracer: false,
+            trail: None,
+            prev_position: Point3 { x, y, z },
+            spin: 0.0,
+            user_placed: false,
+            central: false,
+            precise: None,
+            id: NEXT_PARTICLE_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    // The f64 state to integrate from: the stored one while it still rounds
+    // to this particle's f32 values, otherwise (after a merge, nudge, edge
+    // wrap, ...) the f32 values widened
+    fn precise_state(&self) -> PreciseState {
+        let (p, v) = (self.position, self.velocity);
+        let widened = ([p.x as f64, p.y as f64, p.z as f64], [v.x as f64, v.y as f64, v.z as f64]);
+        match self.precise {
+            Some(state) if state.0.map(|c| c as f32) == [p.x, p.y, p.z] && state.1.map(|c| c as f32) == [v.x, v.y, v.z] => state,
+            _ => widened,
+        }
+    }
+
+    // Drawn position a fraction `alpha` of the way from the previous tick to now
+    fn interpolated_position(&self, alpha: f32) -> Point3<f32> {
+        lerp_point(self.prev_position, self.position, alpha)
+    }
+
+    // Ends of the motion-blur streak: where the particle was at the start of
+    // the last frame's physics, and where it's drawn now
+    fn streak(&self, alpha: f32) -> (Point3<f32>, Point3<f32>) {
+        (self.prev_position, self.interpolated_position(alpha))
+    }
+
+    // Perfectly inelastic merge: mass and momentum are conserved and the
+    // result sits at the pair's center of mass. The pair's angular momentum
+    // about that center, spins included, becomes the merged body's spin.
+    fn absorb(&mut self, other: &Particle, model: RadiusModel) {
+        let (m1, m2) = (self.mass, other.mass);
+        let total = m1 + m2;
+        let (p1, p2) = (self.position, other.position);
+        let (v1, v2) = (self.velocity, other.velocity);
+        self.position = Point3 {
+            x: (p1.x * m1 + p2.x * m2) / total,
+            y: (p1.y * m1 + p2.y * m2) / total,
+            z: (p1.z * m1 + p2.z * m2) / total,
+        };
+        let c = self.position;
+        let spin_momentum = m1 * ((p1.x - c.x) * v1.y - (p1.y - c.y) * v1.x)
+            + m2 * ((p2.x - c.x) * v2.y - (p2.y - c.y) * v2.x)
+            + spin_inertia(m1, self.radius) * self.spin
+            + spin_inertia(m2, other.radius) * other.spin;
+        self.velocity = Vector3 {
+            x: (v1.x * m1 + v2.x * m2) / total,
+            y: (v1.y * m1 + v2.y * m2) / total,
+            z: (v1.z * m1 + v2.z * m2) / total,
+        };
+        // Swallowing an anchor (or being one) pins the result in its place
+        if self.fixed || other.fixed {
+            self.position = if self.fixed { p1 } else { p2 };
+            self.velocity = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+            self.fixed = true;
         }
+        self.mass = total;
+        self.central |= other.central;
+        self.radius = model.radius(total);
+        self.spin = spin_momentum / spin_inertia(total, self.radius);
     }
 
-    fn calculate_acceleration(&mut self, particles: &[Particle], is_3d: bool) {
-        self.acceleration = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+    // `index` is this particle's own slot in `particles`, skipped so it never
+    // pulls on itself, or None when it isn't in there
+    fn calculate_acceleration(&mut self, index: Option<usize>, particles: &[Particle], tree: Option<&QuadNode>, settings: &ForceSettings) {
+        self.acceleration = Vector3 {
+            x: -settings.drag * self.velocity.x,
+            y: -settings.drag * self.velocity.y,
+            z: -settings.drag * self.velocity.z,
+        };
+        if !settings.gravity {
+            return;
+        }
         
-        for other in particles {
-            if std::ptr::eq(self, other) {
-                continue;
-            }
+        if let Some(root) = tree {
+            let global = (!settings.size_softening).then_some(settings.softening * settings.softening);
+            self.add_tree_acceleration(root, index, particles, settings, global);
+        } else {
+            for (i, other) in particles.iter().enumerate() {
+                if Some(i) == index || other.tracer {
+                    continue;
+                }
     
-            let dx = other.position.x - self.position.x;
-            let dy = other.position.y - self.position.y;
-            let dz = other.position.z - self.position.z;
-            let softening = particles[0].mass.log10();
-            let dist_squared = dx * dx + dy * dy + dz * dz + softening;
-            let dist = dist_squared.sqrt();
+                let dx = other.position.x - self.position.x;
+                let dy = other.position.y - self.position.y;
+                let dz = other.position.z - self.position.z;
+                let raw_squared = dx * dx + dy * dy + dz * dz;
+                if settings.cutoff > 0.0 && raw_squared > settings.cutoff * settings.cutoff {
+                    continue;
+                }
+                let epsilon = if settings.size_softening {
+                    pair_softening(self.radius, other.radius)
+                } else {
+                    settings.softening * settings.softening
+                };
+                let dist_squared = raw_squared + epsilon;
+                let dist = dist_squared.sqrt();
     
-            if dist < self.radius + other.radius {
-                continue;
-            }
+                if dist < self.radius + other.radius {
+                    continue;
+                }
     
-            let force = G * other.mass / dist_squared;
+                let force = force_magnitude(other.mass, dist_squared, settings.exponent);
             
-            self.acceleration.x += force * dx / dist;
-            self.acceleration.y += force * dy / dist;
-            if is_3d {
-                self.acceleration.z += force * dz / dist;
+                self.acceleration.x += force * dx / dist;
+                self.acceleration.y += force * dy / dist;
+                if settings.is_3d {
+                    self.acceleration.z += force * dz / dist;
+                }
+            }
+        }
+        // Tames slingshots off close passes; weak distant pulls never reach the cap
+        if settings.max_acceleration > 0.0 {
+            clamp_magnitude(&mut self.acceleration, settings.max_acceleration);
+        }
+    }
+
+    // Barnes-Hut: a cell far enough away relative to its size acts as a
+    // single body at its center of mass, otherwise its children are opened.
+    // `softening` is the global term, or None to soften by size; a cell of
+    // several bodies then counts as a point with no radius of its own
+    fn add_tree_acceleration(&mut self, node: &QuadNode, index: Option<usize>, particles: &[Particle], settings: &ForceSettings, softening: Option<f32>) {
+        if node.mass <= 0.0 || (node.particle.is_some() && node.particle == index) {
+            return;
+        }
+        let dx = node.center_of_mass.x - self.position.x;
+        let dy = node.center_of_mass.y - self.position.y;
+        let raw_squared = dx * dx + dy * dy;
+        let far_enough = node.bounds.w * node.bounds.w < BARNES_HUT_THETA * BARNES_HUT_THETA * raw_squared;
+        match &node.children {
+            Some(children) if !far_enough => {
+                for child in children.iter() {
+                    self.add_tree_acceleration(child, index, particles, settings, softening);
+                }
+            }
+            _ => {
+                if settings.cutoff > 0.0 && raw_squared > settings.cutoff * settings.cutoff {
+                    return;
+                }
+                let other_radius = node.particle.map_or(0.0, |i| particles[i].radius);
+                let dist_squared = raw_squared + softening.unwrap_or_else(|| pair_softening(self.radius, other_radius));
+                let dist = dist_squared.sqrt();
+                // Same overlap skip as the direct sum
+                if dist < self.radius + other_radius {
+                    return;
+                }
+                let force = force_magnitude(node.mass, dist_squared, settings.exponent);
+                self.acceleration.x += force * dx / dist;
+                self.acceleration.y += force * dy / dist;
             }
         }
     }
 
-    fn update(&mut self, dt: f32, particles: &[Particle], is_3d: bool) {
-        self.calculate_acceleration(particles, is_3d);
+    // With `recompute_forces` false the previous acceleration is reused, which
+    // is cheaper but less accurate
+    fn update(&mut self, dt: f32, index: Option<usize>, particles: &[Particle], tree: Option<&QuadNode>, settings: &ForceSettings, recompute_forces: bool) {
+        if self.fixed {
+            self.velocity = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+            return;
+        }
+        if recompute_forces {
+            self.calculate_acceleration(index, particles, tree, settings);
+        }
         // First half-kick
         self.velocity.x += self.acceleration.x * dt * 0.5;
         self.velocity.y += self.acceleration.y * dt * 0.5;
@@ -228,7 +758,9 @@ impl Particle {
         self.position.z += self.velocity.z * dt;
         
         // Update accelerations
-        self.calculate_acceleration(particles, is_3d);
+        if recompute_forces {
+            self.calculate_acceleration(index, particles, tree, settings);
+        }
         
         // Second half-kick
         self.velocity.x += self.acceleration.x * dt * 0.5;
@@ -238,369 +770,6436 @@ impl Particle {
     }
 }
 
-struct SimulationState {
-    particles: Vec<Particle>,
-    particle_count: usize,
-    initial_mass_range: (f32, f32),
-    initial_velocity_multiplier: f32,
-    paused: bool,
-    zoom: f32,
-    pan: Point2<f32>,
-    buttons: Vec<Button>,
-    sliders: Vec<Slider>,
-    is_panning: bool,
-    last_mouse_pos: Point2<f32>,
-    adding_mass: bool,
-    mass_preview: Option<Point2<f32>>,
-    // 3d stuff
-    is_3d: bool,
-    rotation_x: f32,
-    rotation_y: f32,
-    
+// Exponential moving average of how long one phase of a frame takes
+#[derive(Clone, Copy, Default)]
+struct PhaseTiming {
+    average_ms: f32,
+    samples: u32,
 }
 
+impl PhaseTiming {
+    fn record(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_secs_f32() * 1000.0;
+        // The first sample seeds the average instead of easing up from zero
+        self.average_ms = if self.samples == 0 {
+            ms
+        } else {
+            self.average_ms + (ms - self.average_ms) * TIMING_SMOOTHING
+        };
+        self.samples = self.samples.saturating_add(1);
+    }
+}
 
-impl SimulationState {
-    fn add_large_mass(&mut self, x: f32, y: f32) {
-        let mass = self.sliders[3].value * 100.0;
-        self.particles.push(Particle::new(x, y, 0.0, mass));
+// Measures an orbital period by unwrapping a particle's angle about the
+// star and timing each full turn
+struct PeriodTracker {
+    // Which particle this belongs to, for noticing a change of selection
+    index: usize,
+    last: Option<(f32, f32)>, // (angle, time) at the previous observation
+    swept: f32,               // signed angle covered since the last full turn
+    turn_started: f32,
+    period: Option<f32>,
+}
+
+impl PeriodTracker {
+    fn new(index: usize) -> Self {
+        PeriodTracker { index, last: None, swept: 0.0, turn_started: 0.0, period: None }
     }
 
-    fn new() -> Self {
-        let mut state = SimulationState {
-            particles: Vec::new(),
-            particle_count: 100,
-            initial_mass_range: (1.0, 5.0),
-            initial_velocity_multiplier: 1.0,
-            paused: true,
-            zoom: 1.0,
-            pan: Point2 { x: 0.0, y: 0.0 },
-            is_3d: false,
-            rotation_x: 0.0,
-            rotation_y: 0.0,
-            buttons: vec![
-                Button::new(10.0, 10.0, 100.0, 30.0, "Run/Pause"),
-                Button::new(120.0, 10.0, 100.0, 30.0, "Reset"),
-                Button::new(230.0, 10.0, 100.0, 30.0, "Add Mass"),
-                Button::new(340.0, 10.0, 100.0, 30.0, "2D/3D"),
-            ],
-            sliders: vec![
-                Slider::new(1.0, 0.1, 10.0, "Time Speed", 50.0, false),
-                Slider::new(100.0, 10.0, 1000.0, "Particles", 90.0, true),
-                Slider::new(1.0, 0.1, 5.0, "Velocity", 130.0, false),
-                Slider::new(3.0, 0.1, 100.0, "Mass", 170.0, false),
-                Slider::new(1.0, 0.1, 10.0, "Softening", 210.0, false),
-                Slider::new(0.016, 0.001, 0.1, "Time Step", 250.0, false),
-                Slider::new(1000.0, 100.0, 5000.0, "Central Mass", 290.0, false),
-            ],
-            is_panning: false,
-            last_mouse_pos: Point2 { x: 0.0, y: 0.0 },
-            adding_mass: false,
-            mass_preview: None,
+    // Feeds one angle sample; returns the period each time a turn completes
+    fn observe(&mut self, angle: f32, time: f32) -> Option<f32> {
+        let Some((last_angle, last_time)) = self.last.replace((angle, time)) else {
+            self.turn_started = time;
+            return None;
         };
-        state.reset();
-        state
+        // Shortest way round from the previous angle
+        let delta = (angle - last_angle + PI).rem_euclid(2.0 * PI) - PI;
+        self.swept += delta;
+        if self.swept.abs() < 2.0 * PI || delta == 0.0 {
+            return None;
+        }
+        // Interpolate when inside this sample the turn actually finished
+        let overshoot = self.swept.abs() - 2.0 * PI;
+        let crossed_at = time - (time - last_time) * overshoot / delta.abs();
+        self.swept -= 2.0 * PI * self.swept.signum();
+        let period = crossed_at - self.turn_started;
+        self.turn_started = crossed_at;
+        self.period = Some(period);
+        self.period
     }
+}
 
+// Everything needed to put the simulation back to an earlier moment
+struct Snapshot {
+    particles: Vec<Particle>,
+    steps: u64,
+    sim_time: f32,
+    star_mass_history: History,
+}
 
-    fn reset(&mut self) {
-        let mut rng = rand::thread_rng();
-        self.particles.clear();
+impl Snapshot {
+    // Frozen positions and radii, for drawing the snapshot over the live run
+    fn ghost(&self) -> Vec<(Point3<f32>, f32)> {
+        self.particles.iter().map(|particle| (particle.position, particle.radius)).collect()
+    }
+}
 
-        self.particles.push(Particle::new(
-            WINDOW_WIDTH / 2.0,
-            WINDOW_HEIGHT / 2.0,
-            0.0,
-            self.sliders[6].value,
-        ));
+// Drawing-only buffers set aside by the clear-visuals key, kept for undo
+struct Visuals {
+    // (particle index, trail) for every tracked particle
+    trails: Vec<(usize, VecDeque<Point3<f32>>)>,
+    merge_effects: Vec<MergeEffect>,
+    star_mass_history: History,
+    leader_path: VecDeque<Option<Point3<f32>>>,
+}
 
-        for _ in 0..self.particle_count {
-            let (x, y, z, angle, phi, theta, distance) = if self.is_3d {
-                let distance = rng.gen_range(100.0..300.0);
-                let phi = rng.gen_range(0.0..2.0 * PI);
-                let theta = rng.gen_range(0.0..PI);
-                
-                (
-                    WINDOW_WIDTH / 2.0 + distance * phi.sin() * theta.cos(),
-                    WINDOW_HEIGHT / 2.0 + distance * phi.sin() * theta.sin(),
-                    distance * phi.cos(),
-                    0.0,
-                    phi,
-                    theta,
-                    distance
-                )
-            } else {
-                let distance = rng.gen_range(100.0..300.0);
-                let angle = rng.gen_range(0.0..2.0 * PI);
-                (
-                    WINDOW_WIDTH / 2.0 + distance * angle.cos(),
-                    WINDOW_HEIGHT / 2.0 + distance * angle.sin(),
-                    0.0,
-                    angle,
-                    0.0,
-                    0.0,
-                    distance
-                )
-            };
-            
-            let mut particle = Particle::new(
-                x, y, z,
-                rng.gen_range(self.initial_mass_range.0..self.initial_mass_range.1)
-            );
+// Orbiting particles spawn in this ring around the star
+const SPAWN_MIN_RADIUS: f32 = 100.0;
+const SPAWN_MAX_RADIUS: f32 = 300.0;
+// Tries per particle before reset gives up finding a non-overlapping spot
+const SPAWN_ATTEMPTS: usize = 50;
+const BARNES_HUT_THETA: f32 = 0.5; // cell size / distance below which a cell counts as one body
+const BARNES_HUT_CAPACITY_FACTOR: usize = 10;
+const DEFAULT_SAFE_PARTICLES: usize = 400; // brute-force count before frame rate suffers
+const QUADTREE_MAX_DEPTH: usize = 16;
 
-            let orbital_speed = (G * self.particles[0].mass / distance).sqrt() * self.initial_velocity_multiplier;
-            
-            particle.velocity = if self.is_3d {
-                Vector3 {
-                    x: orbital_speed * (-phi.sin() * theta.sin()),
-                    y: orbital_speed * (phi.sin() * theta.cos()),
-                    z: orbital_speed * phi.cos(),
-                }
-            } else {
-                Vector3 {
-                    x: -orbital_speed * angle.sin(),
-                    y: orbital_speed * angle.cos(),
-                    z: 0.0,
-                }
-            };
+// Spatial subdivision of the particles in the x/y plane; every node keeps
+// its total mass and center of mass
+struct QuadNode {
+    bounds: graphics::Rect,
+    mass: f32,
+    center_of_mass: Point2<f32>,
+    particle: Option<usize>,
+    children: Option<Box<[QuadNode; 4]>>,
+}
 
-            self.particles.push(particle);
+impl QuadNode {
+    fn new(bounds: graphics::Rect) -> Self {
+        QuadNode {
+            bounds,
+            mass: 0.0,
+            center_of_mass: Point2 { x: 0.0, y: 0.0 },
+            particle: None,
+            children: None,
         }
     }
 
-    fn handle_mouse_click(&mut self, x: f32, y: f32) {
-        let mouse_pos = Point2 { x, y };
-        
-        // Handle UI elements first
-        let mut clicked_reset = false;
-        let mut should_pause = false;
-        let mut start_add_mass = false;
-        
-        // Only handle UI if not in mass-adding mode
-        if !self.adding_mass {
-        for button in &mut self.buttons {
-            if button.contains(mouse_pos) {
-                button.clicked = true;
-                match button.text.as_str() {
-                    "Run/Pause" => should_pause = true,
-                    "Reset" => clicked_reset = true,
-                    "Add Mass" => start_add_mass = true,
-                    "2D/3D" => self.is_3d = !self.is_3d,
-                    _ => (),
-                }
-                }
-            }
-
-            for slider in &mut self.sliders {
-                if slider.handle_click(x, y) {
-                    match slider.label.as_str() {
-                        "Particles" => self.particle_count = slider.value as usize,
-                        "Velocity" => self.initial_velocity_multiplier = slider.value,
-                        "Mass" => self.initial_mass_range = (slider.value * 0.5, slider.value * 1.5),
-                    _ => (),
-                }
-                    return;
-                }
-            }
+    fn build(particles: &[Particle]) -> Self {
+        let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+        let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+        for particle in particles {
+            min_x = min_x.min(particle.position.x);
+            min_y = min_y.min(particle.position.y);
+            max_x = max_x.max(particle.position.x);
+            max_y = max_y.max(particle.position.y);
         }
-        
-        if should_pause {
-            self.paused = !self.paused;
+        // Square root cell so children stay square
+        let size = (max_x - min_x).max(max_y - min_y).max(1.0);
+        let mut root = QuadNode::new(graphics::Rect::new(min_x, min_y, size, size));
+        for (i, particle) in particles.iter().enumerate() {
+            root.insert(particles, i, Point2 { x: particle.position.x, y: particle.position.y }, 0);
         }
-        if clicked_reset {
-            self.reset();
+        root
+    }
+
+    fn quadrant(&self, point: Point2<f32>) -> usize {
+        let mid_x = self.bounds.x + self.bounds.w / 2.0;
+        let mid_y = self.bounds.y + self.bounds.h / 2.0;
+        match (point.x >= mid_x, point.y >= mid_y) {
+            (false, false) => 0,
+            (true, false) => 1,
+            (false, true) => 2,
+            (true, true) => 3,
         }
-        if start_add_mass {
-            self.adding_mass = true;
-            return;
+    }
+
+    fn subdivide(&mut self) {
+        let (x, y) = (self.bounds.x, self.bounds.y);
+        let half = self.bounds.w / 2.0;
+        self.children = Some(Box::new([
+            QuadNode::new(graphics::Rect::new(x, y, half, half)),
+            QuadNode::new(graphics::Rect::new(x + half, y, half, half)),
+            QuadNode::new(graphics::Rect::new(x, y + half, half, half)),
+            QuadNode::new(graphics::Rect::new(x + half, y + half, half, half)),
+        ]));
+    }
+
+    fn insert(&mut self, particles: &[Particle], index: usize, point: Point2<f32>, depth: usize) {
+        let mass = particles[index].mass;
+        let total = self.mass + mass;
+        if total > 0.0 {
+            self.center_of_mass = Point2 {
+                x: (self.center_of_mass.x * self.mass + point.x * mass) / total,
+                y: (self.center_of_mass.y * self.mass + point.y * mass) / total,
+            };
         }
+        self.mass = total;
 
-        // Handle mass placement or panning
-        if self.adding_mass {
-            if y > 50.0 { // Don't add mass in UI area
-                self.add_large_mass(x, y);
-                self.adding_mass = false;
-                self.mass_preview = None;
+        if self.children.is_none() {
+            if self.particle.is_none() && self.mass == mass {
+                self.particle = Some(index);
+                return;
             }
-        } else {
-            // Start panning if not clicking UI
-            if y > 50.0 {
-                self.is_panning = true;
-                self.last_mouse_pos = mouse_pos;
+            // Coincident particles would subdivide forever, so stop at a depth limit
+            if depth >= QUADTREE_MAX_DEPTH {
+                return;
+            }
+            self.subdivide();
+            if let Some(existing) = self.particle.take() {
+                let existing_point = Point2 {
+                    x: particles[existing].position.x,
+                    y: particles[existing].position.y,
+                };
+                let quadrant = self.quadrant(existing_point);
+                if let Some(children) = &mut self.children {
+                    children[quadrant].insert(particles, existing, existing_point, depth + 1);
+                }
             }
         }
-    }
 
-    fn handle_mouse_release(&mut self) {
-        for button in &mut self.buttons {
-            button.clicked = false;
+        let quadrant = self.quadrant(point);
+        if let Some(children) = &mut self.children {
+            children[quadrant].insert(particles, index, point, depth + 1);
         }
-        self.is_panning = false;
     }
 
-    fn handle_mouse_motion(&mut self, x: f32, y: f32) {
-        let current_pos = Point2 { x, y };
-        
-        if self.is_panning {
-            if self.is_3d {
-                self.rotation_y += (current_pos.x - self.last_mouse_pos.x) * 0.01;
-                self.rotation_x += (current_pos.y - self.last_mouse_pos.y) * 0.01;
-            } else {
-            self.pan.x += (current_pos.x - self.last_mouse_pos.x) / self.zoom;
-            self.pan.y += (current_pos.y - self.last_mouse_pos.y) / self.zoom;
+    fn leaf_count(&self) -> usize {
+        match &self.children {
+            Some(children) => children.iter().map(QuadNode::leaf_count).sum(),
+            None => 1,
+        }
+    }
+
+    fn visit(&self, f: &mut impl FnMut(&QuadNode)) {
+        f(self);
+        if let Some(children) = &self.children {
+            for child in children.iter() {
+                child.visit(f);
             }
-            self.last_mouse_pos = current_pos;
         }
+    }
+}
 
-        if self.adding_mass {
-            self.mass_preview = Some(current_pos);
+// How much of the quadtree the debug overlay shows
+#[derive(Clone, Copy, PartialEq)]
+enum QuadtreeOverlay {
+    Off,
+    Cells,
+    CellsAndMass,
+}
+
+// Draw-time transform that makes the flat disk look viewed at an angle:
+// squash vertically about the screen center and shrink the far (upper)
+// side a little for perspective
+fn tilt_projection(point: Point2<f32>) -> Point2<f32> {
+    let center = Point2 { x: WINDOW_WIDTH / 2.0, y: WINDOW_HEIGHT / 2.0 };
+    let dy = point.y - center.y;
+    let depth = 1.0 + TILT_PERSPECTIVE * dy / WINDOW_HEIGHT;
+    Point2 {
+        x: center.x + (point.x - center.x) * depth,
+        y: center.y + dy * TILT_FORESHORTENING * depth,
+    }
+}
+
+// Marching squares over a row-major scalar grid, returning the iso-line
+// segments for `level` in fractional grid coordinates (column, row).
+// Saddle cells are resolved the same way every time, which is fine for an overlay.
+fn marching_squares(field: &[f32], cols: usize, rows: usize, level: f32) -> Vec<[Point2<f32>; 2]> {
+    let mut segments = Vec::new();
+    if cols < 2 || rows < 2 {
+        return segments;
+    }
+    let at = |c: usize, r: usize| field[r * cols + c];
+    // Where the level crosses the edge between two corners
+    let lerp = |a: (f32, f32, f32), b: (f32, f32, f32)| {
+        let t = if (b.2 - a.2).abs() > f32::EPSILON { (level - a.2) / (b.2 - a.2) } else { 0.5 };
+        Point2 { x: a.0 + (b.0 - a.0) * t, y: a.1 + (b.1 - a.1) * t }
+    };
+
+    for r in 0..rows - 1 {
+        for c in 0..cols - 1 {
+            let (x, y) = (c as f32, r as f32);
+            let tl = (x, y, at(c, r));
+            let tr = (x + 1.0, y, at(c + 1, r));
+            let br = (x + 1.0, y + 1.0, at(c + 1, r + 1));
+            let bl = (x, y + 1.0, at(c, r + 1));
+            let case = (tl.2 > level) as u8
+                | ((tr.2 > level) as u8) << 1
+                | ((br.2 > level) as u8) << 2
+                | ((bl.2 > level) as u8) << 3;
+            let top = || lerp(tl, tr);
+            let right = || lerp(tr, br);
+            let bottom = || lerp(bl, br);
+            let left = || lerp(tl, bl);
+            match case {
+                0 | 15 => {}
+                1 | 14 => segments.push([left(), top()]),
+                2 | 13 => segments.push([top(), right()]),
+                3 | 12 => segments.push([left(), right()]),
+                4 | 11 => segments.push([right(), bottom()]),
+                6 | 9 => segments.push([top(), bottom()]),
+                7 | 8 => segments.push([left(), bottom()]),
+                5 => {
+                    segments.push([left(), top()]);
+                    segments.push([right(), bottom()]);
+                }
+                10 => {
+                    segments.push([top(), right()]);
+                    segments.push([left(), bottom()]);
+                }
+                _ => unreachable!(),
+            }
         }
     }
+    segments
+}
+
+// Step count and camera (pan x/y, zoom) plus particle count a contour set
+// was computed for
+type ContourKey = (u64, f32, f32, f32, usize);
 
+// Keplerian shape of a bound two-body orbit in the x/y plane
+struct OrbitalElements {
+    semi_major_axis: f32,
+    eccentricity: f32,
+    // Direction of periapsis from the central body, in radians
+    periapsis_angle: f32,
 }
 
-// Update draw() to handle 3D projection:
-impl EventHandler for SimulationState {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult {
-        if !self.paused {
-            let time_speed = self.sliders[0].value;
-            let dt = DT * time_speed;
-            let particles_snapshot = self.particles.clone();
-            for particle in &mut self.particles {
-                particle.update(dt, &particles_snapshot, self.is_3d);
+impl OrbitalElements {
+    // From position and velocity relative to the central body, with
+    // mu = G * (central + body mass). None for unbound orbits.
+    fn from_state(position: Point2<f32>, velocity: Point2<f32>, mu: f32) -> Option<Self> {
+        let distance = (position.x * position.x + position.y * position.y).sqrt();
+        if distance <= f32::EPSILON || mu <= 0.0 {
+            return None;
+        }
+        let energy = 0.5 * (velocity.x * velocity.x + velocity.y * velocity.y) - mu / distance;
+        if energy >= 0.0 {
+            return None;
+        }
+        let angular_momentum = position.x * velocity.y - position.y * velocity.x;
+        // Eccentricity vector, pointing at periapsis
+        let ex = velocity.y * angular_momentum / mu - position.x / distance;
+        let ey = -velocity.x * angular_momentum / mu - position.y / distance;
+        Some(OrbitalElements {
+            semi_major_axis: -mu / (2.0 * energy),
+            eccentricity: (ex * ex + ey * ey).sqrt(),
+            periapsis_angle: ey.atan2(ex),
+        })
+    }
+
+    // Position relative to the central body at the given true anomaly
+    fn point_at(&self, true_anomaly: f32) -> Point2<f32> {
+        let e = self.eccentricity;
+        let r = self.semi_major_axis * (1.0 - e * e) / (1.0 + e * true_anomaly.cos());
+        let angle = self.periapsis_angle + true_anomaly;
+        Point2 { x: r * angle.cos(), y: r * angle.sin() }
+    }
+}
+
+// Fraction of the total pull on particles[index] that comes from `star`;
+// near 1 means the two-body approximation holds
+fn star_dominance(particles: &[Particle], index: usize, star: usize, exponent: f32) -> f32 {
+    let target = &particles[index];
+    let mut total = 0.0;
+    let mut from_star = 0.0;
+    for (i, other) in particles.iter().enumerate() {
+        if i == index {
+            continue;
+        }
+        let dx = other.position.x - target.position.x;
+        let dy = other.position.y - target.position.y;
+        let dz = other.position.z - target.position.z;
+        let force = force_magnitude(other.mass, dx * dx + dy * dy + dz * dz, exponent);
+        total += force;
+        if i == star {
+            from_star = force;
+        }
+    }
+    if total > 0.0 { from_star / total } else { 0.0 }
+}
+
+// Path of particles[index] integrated forward with everything else held
+// still, for orbits too perturbed to draw as an ellipse
+fn predict_path(particles: &[Particle], index: usize, settings: &ForceSettings, dt: f32, steps: usize) -> Vec<Point3<f32>> {
+    let others: Vec<Particle> = particles
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, particle)| particle.clone())
+        .collect();
+    let mut body = particles[index].clone();
+    body.fixed = false;
+    (0..steps)
+        .map(|_| {
+            body.update(dt, None, &others, None, settings, true);
+            body.position
+        })
+        .collect()
+}
+
+// Tip of an arrow of `length` pixels from `from` along the direction
+// `toward` - `from`; `from` itself when there's no direction
+fn arrow_tip(from: Point2<f32>, toward: Point2<f32>, length: f32) -> Point2<f32> {
+    let (dx, dy) = (toward.x - from.x, toward.y - from.y);
+    let norm = (dx * dx + dy * dy).sqrt();
+    if norm <= f32::EPSILON {
+        return from;
+    }
+    Point2 { x: from.x + dx / norm * length, y: from.y + dy / norm * length }
+}
+
+// How far a background point `distance` pixels from a lens is pushed
+// outward, using the point-lens image position: it tends to the Einstein
+// radius right behind the lens and falls off as 1/distance far from it
+fn lensing_displacement(einstein_radius: f32, distance: f32) -> f32 {
+    let distance = distance.max(0.0);
+    ((distance * distance + 4.0 * einstein_radius * einstein_radius).sqrt() - distance) / 2.0
+}
+
+// Straight line with a two-stroke head at `to`
+fn draw_arrow(ctx: &mut Context, canvas: &mut graphics::Canvas, from: Point2<f32>, to: Point2<f32>, color: Color) -> GameResult {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length < 1.0 {
+        return Ok(());
+    }
+    let (ux, uy) = (dx / length, dy / length);
+    let head = length.min(8.0);
+    let left = Point2 { x: to.x - head * (ux + uy * 0.5), y: to.y - head * (uy - ux * 0.5) };
+    let right = Point2 { x: to.x - head * (ux - uy * 0.5), y: to.y - head * (uy + ux * 0.5) };
+    let mut builder = graphics::MeshBuilder::new();
+    builder.line(&[from, to], 1.5, color)?;
+    builder.line(&[left, to, right], 1.5, color)?;
+    let arrow = Mesh::from_data(ctx, builder.build());
+    canvas.draw(&arrow, DrawParam::default());
+    Ok(())
+}
+
+// Faint background dot; brightness doubles as alpha
+struct BackgroundStar {
+    position: Point2<f32>,
+    brightness: f32,
+}
+
+// Same seed, same sky, so the background never reshuffles between runs
+fn generate_starfield(seed: u64, count: usize) -> Vec<BackgroundStar> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| BackgroundStar {
+            position: Point2 {
+                x: rng.gen_range(0.0..WINDOW_WIDTH),
+                y: rng.gen_range(0.0..WINDOW_HEIGHT),
+            },
+            brightness: rng.gen_range(0.15..0.5),
+        })
+        .collect()
+}
+
+fn parallax_offset(pan: Point2<f32>) -> Point2<f32> {
+    Point2 {
+        x: pan.x * STARFIELD_PARALLAX,
+        y: pan.y * STARFIELD_PARALLAX,
+    }
+}
+
+// Bounded time series of (step, value). Once full, every other sample is
+// dropped and the recording stride doubles, so memory stays fixed while the
+// whole run remains covered.
+#[derive(Clone)]
+struct History {
+    samples: Vec<(u64, f32)>,
+    stride: u64,
+}
+
+impl History {
+    fn new() -> Self {
+        History { samples: Vec::new(), stride: 1 }
+    }
+
+    fn record(&mut self, step: u64, value: f32) {
+        if !step.is_multiple_of(self.stride) {
+            return;
+        }
+        self.samples.push((step, value));
+        if self.samples.len() > HISTORY_CAPACITY {
+            let mut keep = false;
+            self.samples.retain(|_| {
+                keep = !keep;
+                keep
+            });
+            self.stride *= 2;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+        self.stride = 1;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CollisionKind {
+    // Two ordinary bodies merged
+    Merge,
+    // Something fell into the central star
+    Accretion,
+    // Too fast to stick; both bodies survive with reflected velocities
+    Bounce,
+}
+
+// Emitted by the physics for every collision so presentation can react to
+// it. For merges `a` absorbed `b`; both are indices from before the step's
+// removals.
+#[derive(Clone, Copy)]
+struct CollisionEvent {
+    a: usize,
+    b: usize,
+    kind: CollisionKind,
+    // Where the absorbed body was when it hit
+    position: Point3<f32>,
+}
+
+// Where `index` (counted before the step's removals) ended up after the
+// step's merges, or None if nothing happened to it. Follows chains where the
+// absorbing body was itself absorbed later in the same step.
+fn surviving_index(events: &[CollisionEvent], index: usize) -> Option<usize> {
+    let merges: Vec<&CollisionEvent> = events.iter().filter(|event| event.kind != CollisionKind::Bounce).collect();
+    if !merges.iter().any(|event| event.a == index || event.b == index) {
+        return None;
+    }
+    let mut current = index;
+    while let Some(event) = merges.iter().find(|event| event.b == current) {
+        current = event.a;
+    }
+    Some(current - merges.iter().filter(|event| event.b < current).count())
+}
+
+fn relative_speed(a: &Particle, b: &Particle) -> f32 {
+    let dvx = b.velocity.x - a.velocity.x;
+    let dvy = b.velocity.y - a.velocity.y;
+    let dvz = b.velocity.z - a.velocity.z;
+    (dvx * dvx + dvy * dvy + dvz * dvz).sqrt()
+}
+
+// Elastic collision along the line of centers, then pushes the pair apart so
+// they only just touch. Fixed anchors behave as if infinitely heavy.
+fn elastic_bounce(a: &mut Particle, b: &mut Particle) {
+    let dx = b.position.x - a.position.x;
+    let dy = b.position.y - a.position.y;
+    let dz = b.position.z - a.position.z;
+    let dist = (dx * dx + dy * dy + dz * dz).sqrt();
+    let (nx, ny, nz) = if dist > f32::EPSILON { (dx / dist, dy / dist, dz / dist) } else { (1.0, 0.0, 0.0) };
+    let inverse_mass = |p: &Particle| if p.fixed { 0.0 } else { 1.0 / p.mass };
+    let (inv_a, inv_b) = (inverse_mass(a), inverse_mass(b));
+    if inv_a + inv_b <= 0.0 {
+        return;
+    }
+
+    // Only reflect if they're still approaching each other
+    let closing = (a.velocity.x - b.velocity.x) * nx + (a.velocity.y - b.velocity.y) * ny + (a.velocity.z - b.velocity.z) * nz;
+    if closing > 0.0 {
+        let impulse = 2.0 * closing / (inv_a + inv_b);
+        a.velocity.x -= impulse * inv_a * nx;
+        a.velocity.y -= impulse * inv_a * ny;
+        a.velocity.z -= impulse * inv_a * nz;
+        b.velocity.x += impulse * inv_b * nx;
+        b.velocity.y += impulse * inv_b * ny;
+        b.velocity.z += impulse * inv_b * nz;
+    }
+
+    let overlap = a.radius + b.radius - dist;
+    if overlap > 0.0 {
+        let (share_a, share_b) = (inv_a / (inv_a + inv_b), inv_b / (inv_a + inv_b));
+        a.position.x -= overlap * share_a * nx;
+        a.position.y -= overlap * share_a * ny;
+        a.position.z -= overlap * share_a * nz;
+        b.position.x += overlap * share_b * nx;
+        b.position.y += overlap * share_b * ny;
+        b.position.z += overlap * share_b * nz;
+    }
+}
+
+// Short-lived ripple drawn where a particle fell into the star
+struct MergeEffect {
+    position: Point3<f32>,
+    age: u32,
+}
+
+fn overlapping(a: &Particle, b: &Particle) -> bool {
+    collides(a, b, 1.0, 0.0)
+}
+
+// How much gravity widens a collision target: slow approaches get bent onto
+// it from further out. `strength` 1 is the textbook sqrt(1 + v_esc²/v_rel²),
+// 0 turns focusing off, and the result is capped at FOCUSING_MAX.
+fn focusing_factor(relative_speed: f32, escape_speed: f32, strength: f32) -> f32 {
+    if strength <= 0.0 {
+        return 1.0;
+    }
+    let ratio = escape_speed / relative_speed.max(f32::EPSILON);
+    (1.0 + strength * ratio * ratio).sqrt().min(FOCUSING_MAX)
+}
+
+// Whether two bodies are close enough to collide when each one's collision
+// radius is `cross_section` times its physical radius, widened by
+// gravitational focusing of the given strength
+fn collides(a: &Particle, b: &Particle, cross_section: f32, focusing: f32) -> bool {
+    let dx = b.position.x - a.position.x;
+    let dy = b.position.y - a.position.y;
+    let dz = b.position.z - a.position.z;
+    let contact = a.radius + b.radius;
+    let mut reach = contact * cross_section;
+    if focusing > 0.0 {
+        let escape = escape_velocity(a.mass + b.mass, contact);
+        reach *= focusing_factor(relative_speed(a, b), escape, focusing);
+    }
+    dx * dx + dy * dy + dz * dz < reach * reach
+}
+
+// Draws candidates until one overlaps nothing in `existing`, giving up after
+// `attempts` tries so a crowded field can't loop forever
+fn sample_clear_of(existing: &[Particle], attempts: usize, mut sample: impl FnMut() -> Particle) -> Option<Particle> {
+    (0..attempts)
+        .map(|_| sample())
+        .find(|candidate| existing.iter().all(|other| !overlapping(candidate, other)))
+}
+
+// Every overlapping (i, j) pair with i < j, checking all pairs. Cheapest for
+// small systems.
+fn colliding_pairs_brute(particles: &[Particle], cross_section: f32, focusing: f32) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for i in 0..particles.len() {
+        for j in (i + 1)..particles.len() {
+            if collides(&particles[i], &particles[j], cross_section, focusing) {
+                pairs.push((i, j));
             }
         }
-        Ok(())
     }
+    pairs
+}
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let mut canvas = graphics::Canvas::from_frame(ctx, Color::BLACK);
-    
-        // Draw particles
-        for particle in &self.particles {
-            let pos = if self.is_3d {
-                particle.position.project_to_2d(self.zoom, self.rotation_x, self.rotation_y)
-            } else {
-                Point2 {
-                    x: (particle.position.x + self.pan.x) * self.zoom,
-                    y: (particle.position.y + self.pan.y) * self.zoom,
+// Same pairs as colliding_pairs_brute, found with a uniform spatial hash.
+// Cells are as wide as the largest collision diameter, so colliding particles
+// always share a cell or sit in neighbouring ones.
+fn colliding_pairs_grid(particles: &[Particle], cross_section: f32, focusing: f32) -> Vec<(usize, usize)> {
+    let max_radius = particles.iter().map(|particle| particle.radius).fold(0.0, f32::max);
+    let max_focus = if focusing > 0.0 { FOCUSING_MAX } else { 1.0 };
+    let cell_size = (2.0 * max_radius * cross_section * max_focus).max(f32::EPSILON);
+    let cell_of = |particle: &Particle| {
+        (
+            (particle.position.x / cell_size).floor() as i32,
+            (particle.position.y / cell_size).floor() as i32,
+            (particle.position.z / cell_size).floor() as i32,
+        )
+    };
+
+    let mut grid: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+    for (i, particle) in particles.iter().enumerate() {
+        grid.entry(cell_of(particle)).or_default().push(i);
+    }
+
+    let mut pairs = Vec::new();
+    for (i, particle) in particles.iter().enumerate() {
+        let (cx, cy, cz) = cell_of(particle);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(bucket) = grid.get(&(cx + dx, cy + dy, cz + dz)) else {
+                        continue;
+                    };
+                    for &j in bucket {
+                        if j > i && collides(particle, &particles[j], cross_section, focusing) {
+                            pairs.push((i, j));
+                        }
+                    }
                 }
-            };
-    
-            let circle = Mesh::new_circle(
-                ctx,
-                graphics::DrawMode::fill(),
-                pos,
-                particle.radius * self.zoom,
-                0.1,
-                Color::WHITE,
-            )?;
-            canvas.draw(&circle, DrawParam::default());
-        }
-    
-        // Draw mass preview
-        if self.adding_mass {
-            if let Some(pos) = self.mass_preview {
-                let preview_circle = Mesh::new_circle(
-                    ctx,
-                    graphics::DrawMode::stroke(2.0),
-                    pos,
-                    (self.sliders[3].value * 0.3).max(2.0),
-                    0.1,
-                    Color::YELLOW,
-                )?;
-                canvas.draw(&preview_circle, DrawParam::default());
             }
         }
-    
-        // Draw UI elements
-        for button in &self.buttons {
-            button.draw(ctx, &mut canvas)?;
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+// Speed relative to the central body for an orbit with the given
+// eccentricity, placed at periapsis. Eccentricity 0 gives a circular orbit.
+fn orbit_velocity(central_mass: f32, body_mass: f32, separation: f32, eccentricity: f32) -> f32 {
+    (G * (central_mass + body_mass) * (1.0 + eccentricity) / separation).sqrt()
+}
+
+// Two stars on a circular orbit about their common center of mass, which
+// sits at `center` and stays at rest
+fn binary_star_pair(center: Point2<f32>, mass_a: f32, mass_b: f32, separation: f32) -> [Particle; 2] {
+    let total = mass_a + mass_b;
+    let relative_speed = orbit_velocity(mass_a, mass_b, separation, 0.0);
+    // Each star's share of the separation and speed is the other's mass fraction
+    let mut a = Particle::new(center.x - separation * mass_b / total, center.y, 0.0, mass_a);
+    let mut b = Particle::new(center.x + separation * mass_a / total, center.y, 0.0, mass_b);
+    a.velocity = Vector3 { x: 0.0, y: -relative_speed * mass_b / total, z: 0.0 };
+    b.velocity = Vector3 { x: 0.0, y: relative_speed * mass_a / total, z: 0.0 };
+    [a, b]
+}
+
+// `count` equal stars sharing `total_mass`, evenly spaced on a ring around
+// `center` with neighbours `separation` apart, each on the circular orbit the
+// others' pull supports. A single star just sits at `center`.
+fn central_stars(center: Point2<f32>, total_mass: f32, count: usize, separation: f32) -> Vec<Particle> {
+    let count = count.max(1);
+    let mass = total_mass / count as f32;
+    if count == 1 {
+        return vec![Particle::new(center.x, center.y, 0.0, mass)];
+    }
+    let step = PI / count as f32;
+    let ring = separation / (2.0 * step.sin());
+    // Sum of the inward pulls of the other stars, each at distance 2·ring·sin(k·step)
+    let pull: f32 = (1..count).map(|k| 1.0 / (k as f32 * step).sin()).sum();
+    let speed = (G * mass * pull / (4.0 * ring)).sqrt();
+    (0..count)
+        .map(|i| {
+            let angle = 2.0 * step * i as f32;
+            let mut star = Particle::new(center.x + ring * angle.cos(), center.y + ring * angle.sin(), 0.0, mass);
+            star.velocity = Vector3 { x: -speed * angle.sin(), y: speed * angle.cos(), z: 0.0 };
+            star
+        })
+        .collect()
+}
+
+// Steps of the two-click orbit builder
+#[derive(Clone, Copy, PartialEq)]
+enum OrbitBuilder {
+    Off,
+    // Next click picks an existing body or places a new star
+    PickStar,
+    // Next click places a planet around the selected star
+    PlacePlanet,
+}
+
+// Finds the particle exerting the strongest pull on particles[index],
+// returning its index and the force magnitude
+fn dominant_attractor(particles: &[Particle], index: usize) -> Option<(usize, f32)> {
+    let target = particles.get(index)?;
+    let mut best: Option<(usize, f32)> = None;
+    for (i, other) in particles.iter().enumerate() {
+        if i == index {
+            continue;
         }
-    
-        for slider in &self.sliders {
-            slider.draw(ctx, &mut canvas)?;
+        let dx = other.position.x - target.position.x;
+        let dy = other.position.y - target.position.y;
+        let dz = other.position.z - target.position.z;
+        let dist_squared = dx * dx + dy * dy + dz * dz;
+        if dist_squared <= f32::EPSILON {
+            continue;
         }
-    
-        // Draw mode indicator
-        let mode_text = if self.adding_mass {
-            "Click to place mass"
-        } else if self.is_3d {
-            "Click and drag to rotate"
-        } else {
-            "Click and drag to pan"
+        let force = G * target.mass * other.mass / dist_squared;
+        if best.is_none_or(|(_, strongest)| force > strongest) {
+            best = Some((i, force));
+        }
+    }
+    best
+}
+
+// Standard normal sample via Box-Muller, since rand alone has no Gaussian
+fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+// Adds an independent N(0, sigma) kick to each velocity component of every
+// free particle; the same seed always gives the same kicks
+fn shake_velocities(particles: &mut [Particle], sigma: f32, is_3d: bool, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for particle in particles.iter_mut().filter(|particle| !particle.fixed) {
+        particle.velocity.x += sigma * gaussian(&mut rng);
+        particle.velocity.y += sigma * gaussian(&mut rng);
+        if is_3d {
+            particle.velocity.z += sigma * gaussian(&mut rng);
+        }
+    }
+}
+
+type CameraBookmark = (Point2<f32>, f32); // (pan, zoom)
+
+// One "slot,pan_x,pan_y,zoom" line per saved bookmark, slots counted from 1
+fn format_bookmarks(bookmarks: &[Option<CameraBookmark>]) -> String {
+    bookmarks
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, bookmark)| bookmark.map(|(pan, zoom)| format!("{},{},{},{}\n", slot + 1, pan.x, pan.y, zoom)))
+        .collect()
+}
+
+// Reverse of format_bookmarks; malformed lines and unknown slots are skipped
+fn parse_bookmarks(contents: &str) -> [Option<CameraBookmark>; BOOKMARK_SLOTS] {
+    let mut bookmarks = [None; BOOKMARK_SLOTS];
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [slot, x, y, zoom] = fields[..] else {
+            continue;
         };
-        let text = Text::new(mode_text);
-        canvas.draw(&text, DrawParam::default().dest([500.0, 15.0]).color(Color::WHITE));
-    
-        canvas.finish(ctx)?;
-        Ok(())
+        if let (Ok(slot), Ok(x), Ok(y), Ok(zoom)) = (slot.parse::<usize>(), x.parse(), y.parse(), zoom.parse::<f32>()) {
+            if (1..=BOOKMARK_SLOTS).contains(&slot) && zoom > 0.0 {
+                bookmarks[slot - 1] = Some((Point2 { x, y }, zoom));
+            }
+        }
     }
+    bookmarks
+}
 
-    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) -> GameResult {
-        self.handle_mouse_motion(x, y);
-        Ok(())
+// How gravity is summed each step
+#[derive(Clone, Copy, PartialEq)]
+enum ForceMode {
+    // Every pair, O(n²) but exact
+    BruteForce,
+    // Quadtree approximation, O(n log n); 2D only
+    BarnesHut,
+}
+
+// Most particles each force mode handles before the frame rate suffers
+fn safe_particle_limit(mode: ForceMode, brute_force_limit: usize) -> usize {
+    match mode {
+        ForceMode::BruteForce => brute_force_limit,
+        ForceMode::BarnesHut => brute_force_limit * BARNES_HUT_CAPACITY_FACTOR,
     }
+}
 
-    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult {
-        if button == MouseButton::Left {
-            self.handle_mouse_click(x, y);
+// What happens to particles that leave the world bounds
+#[derive(Clone, Copy, PartialEq)]
+enum BoundaryMode {
+    // Space goes on forever; the bounds are only drawn
+    Open,
+    // Leaving one edge re-enters from the opposite one
+    Wrap,
+    // The edges reflect particles like walls
+    Bounce,
+}
+
+// How smooth circles are drawn; coarser circles are cheaper to build
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum RenderQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl RenderQuality {
+    // Largest allowed gap, in pixels, between a drawn circle and the true one
+    fn tolerance(self) -> f32 {
+        match self {
+            RenderQuality::Low => 0.5,
+            RenderQuality::Medium => 0.1,
+            RenderQuality::High => 0.02,
         }
-        Ok(())
     }
 
-    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) -> GameResult {
-        if button == MouseButton::Left {
-            self.handle_mouse_release();
+    fn finer(self) -> Self {
+        match self {
+            RenderQuality::Low => RenderQuality::Medium,
+            _ => RenderQuality::High,
         }
-        Ok(())
     }
 
-    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
-        self.zoom *= if y > 0.0 { 1.1 } else { 0.9 };
-        Ok(())
+    fn coarser(self) -> Self {
+        match self {
+            RenderQuality::High => RenderQuality::Medium,
+            _ => RenderQuality::Low,
+        }
     }
+}
 
-    fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, _repeat: bool) -> GameResult {
-        match input.keycode {
-            Some(KeyCode::Space) => self.paused = !self.paused,
-            Some(KeyCode::R) => self.reset(),
-            Some(KeyCode::W) => self.pan.y += 10.0 / self.zoom,
-            Some(KeyCode::S) => self.pan.y -= 10.0 / self.zoom,
-            Some(KeyCode::A) => self.pan.x += 10.0 / self.zoom,
-            Some(KeyCode::D) => self.pan.x -= 10.0 / self.zoom,
-            _ => (),
+// Which kind of cloud reset builds around the star
+#[derive(Clone, Copy, PartialEq)]
+enum Scenario {
+    // Flat, rotating disk on near-circular orbits
+    AccretionDisk,
+    // Round cloud with random velocities and no net rotation
+    UniformCloud,
+}
+
+// Keeps a particle inside `bounds` (in world x/y) according to `mode`
+fn apply_boundary(particle: &mut Particle, mode: BoundaryMode, bounds: graphics::Rect) {
+    match mode {
+        BoundaryMode::Open => {}
+        BoundaryMode::Wrap => {
+            let before = particle.position;
+            particle.position.x = bounds.x + (particle.position.x - bounds.x).rem_euclid(bounds.w);
+            particle.position.y = bounds.y + (particle.position.y - bounds.y).rem_euclid(bounds.h);
+            // Shift the previous position along so smooth motion doesn't streak across the world
+            particle.prev_position.x += particle.position.x - before.x;
+            particle.prev_position.y += particle.position.y - before.y;
+        }
+        BoundaryMode::Bounce => {
+            let (left, right, top, bottom) = (bounds.left(), bounds.right(), bounds.top(), bounds.bottom());
+            if particle.position.x < left || particle.position.x > right {
+                particle.position.x = particle.position.x.clamp(left, right);
+                particle.velocity.x = -particle.velocity.x;
+            }
+            if particle.position.y < top || particle.position.y > bottom {
+                particle.position.y = particle.position.y.clamp(top, bottom);
+                particle.velocity.y = -particle.velocity.y;
+            }
         }
-        Ok(())
     }
+}
 
-    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
-        if let Some(text_input) = &mut self.sliders[1].text_input {
-            if character.is_numeric() || character == '\x08' {
-                if character == '\x08' {
-                    text_input.pop();
-                } else {
-                    text_input.push(character);
-                }
-                if let Ok(value) = text_input.parse::<f32>() {
-                    if value >= self.sliders[1].min && value <= self.sliders[1].max {
-                        self.sliders[1].value = value;
-                        self.particle_count = value as usize;
-                    }
-                }
+// `point` turned by `angle` radians about `center`; positive is clockwise on
+// screen, since y points down
+fn rotate_about(point: Point2<f32>, center: Point2<f32>, angle: f32) -> Point2<f32> {
+    let (sin, cos) = angle.sin_cos();
+    let (dx, dy) = (point.x - center.x, point.y - center.y);
+    Point2 { x: center.x + dx * cos - dy * sin, y: center.y + dx * sin + dy * cos }
+}
+
+// Pull of the cursor attractor on a body at `position`: like a point mass of
+// `strength` at `target`, softened so bodies passing through it aren't flung
+// out. Negative strength pushes away instead.
+fn gun_acceleration(position: Point3<f32>, target: Point3<f32>, strength: f32) -> Vector3<f32> {
+    let (dx, dy, dz) = (target.x - position.x, target.y - position.y, target.z - position.z);
+    let soft = dx * dx + dy * dy + dz * dz + GUN_SOFTENING * GUN_SOFTENING;
+    let scale = G * strength / (soft * soft.sqrt());
+    Vector3 { x: dx * scale, y: dy * scale, z: dz * scale }
+}
+
+// Linear blend of two points: `from` at t = 0, `to` at t = 1
+fn lerp_point(from: Point3<f32>, to: Point3<f32>, t: f32) -> Point3<f32> {
+    Point3 {
+        x: from.x + (to.x - from.x) * t,
+        y: from.y + (to.y - from.y) * t,
+        z: from.z + (to.z - from.z) * t,
+    }
+}
+
+// Smoothstep-style cubic ease: starts and ends gently, 0 at t <= 0, 1 at t >= 1
+fn ease_in_out(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// An animated camera move from one zoom/pan to another
+struct CameraFlight {
+    from_zoom: f32,
+    from_pan: Point2<f32>,
+    to_zoom: f32,
+    to_pan: Point2<f32>,
+    elapsed: f32,
+}
+
+impl CameraFlight {
+    fn done(&self) -> bool {
+        self.elapsed >= CAMERA_FLIGHT_DURATION
+    }
+
+    // Zoom and pan at the current point of the flight. Zoom eases in log
+    // space so zooming far in or out feels even.
+    fn current(&self) -> (f32, Point2<f32>) {
+        let t = ease_in_out(self.elapsed / CAMERA_FLIGHT_DURATION);
+        let zoom = (self.from_zoom.ln() + (self.to_zoom.ln() - self.from_zoom.ln()) * t).exp();
+        // Interpolate the world point at the screen center rather than the
+        // raw pan, so the view doesn't swing sideways while zooming
+        let center = |zoom: f32, pan: Point2<f32>| Point2 {
+            x: WINDOW_WIDTH / 2.0 / zoom - pan.x,
+            y: WINDOW_HEIGHT / 2.0 / zoom - pan.y,
+        };
+        let (from, to) = (center(self.from_zoom, self.from_pan), center(self.to_zoom, self.to_pan));
+        let x = from.x + (to.x - from.x) * t;
+        let y = from.y + (to.y - from.y) * t;
+        (zoom, Point2 { x: WINDOW_WIDTH / 2.0 / zoom - x, y: WINDOW_HEIGHT / 2.0 / zoom - y })
+    }
+}
+
+// Particle indices, heaviest first
+fn rank_by_mass(particles: &[Particle]) -> Vec<usize> {
+    let mut ranking: Vec<usize> = (0..particles.len()).collect();
+    ranking.sort_by(|&a, &b| particles[b].mass.total_cmp(&particles[a].mass));
+    ranking
+}
+
+// None when nothing has mass (an empty scene, or only tracers)
+fn center_of_mass(particles: &[Particle]) -> Option<Point3<f32>> {
+    let total: f32 = particles.iter().map(|p| p.mass).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let weighted = particles.iter().fold((0.0, 0.0, 0.0), |(x, y, z), p| {
+        (x + p.position.x * p.mass, y + p.position.y * p.mass, z + p.position.z * p.mass)
+    });
+    Some(Point3 { x: weighted.0 / total, y: weighted.1 / total, z: weighted.2 / total })
+}
+
+// Distance from `center` to the farthest particle center
+fn enclosing_radius(particles: &[Particle], center: Point3<f32>) -> f32 {
+    particles
+        .iter()
+        .map(|p| {
+            let dx = p.position.x - center.x;
+            let dy = p.position.y - center.y;
+            let dz = p.position.z - center.z;
+            (dx * dx + dy * dy + dz * dz).sqrt()
+        })
+        .fold(0.0, f32::max)
+}
+
+fn kinetic_energy(particles: &[Particle]) -> f32 {
+    particles
+        .iter()
+        .map(|p| 0.5 * p.mass * (p.velocity.x * p.velocity.x + p.velocity.y * p.velocity.y + p.velocity.z * p.velocity.z))
+        .sum()
+}
+
+// Newtonian pairwise potential, ignoring softening and the force exponent
+fn potential_energy(particles: &[Particle]) -> f32 {
+    let mut total = 0.0;
+    for (i, a) in particles.iter().enumerate() {
+        for b in &particles[i + 1..] {
+            let dx = b.position.x - a.position.x;
+            let dy = b.position.y - a.position.y;
+            let dz = b.position.z - a.position.z;
+            let dist = (dx * dx + dy * dy + dz * dz).sqrt();
+            if dist > f32::EPSILON {
+                total -= G * a.mass * b.mass / dist;
             }
         }
-        Ok(())
     }
+    total
 }
 
-fn main() -> GameResult {
-    let cb = ggez::ContextBuilder::new("solar_system", "user")
-        .window_setup(ggez::conf::WindowSetup::default().title("Solar System Formation Simulator"))
-        .window_mode(ggez::conf::WindowMode::default().dimensions(WINDOW_WIDTH, WINDOW_HEIGHT));
-    
-    let (ctx, event_loop) = cb.build()?;
-    let state = SimulationState::new();
-    
+fn total_momentum(particles: &[Particle]) -> Vector3<f32> {
+    particles.iter().fold(Vector3 { x: 0.0, y: 0.0, z: 0.0 }, |sum, p| Vector3 {
+        x: sum.x + p.mass * p.velocity.x,
+        y: sum.y + p.mass * p.velocity.y,
+        z: sum.z + p.mass * p.velocity.z,
+    })
+}
+
+// z component of the angular momentum about the world origin
+fn angular_momentum_z(particles: &[Particle]) -> f32 {
+    particles
+        .iter()
+        .map(|p| p.mass * (p.position.x * p.velocity.y - p.position.y * p.velocity.x))
+        .sum()
+}
+
+// Total mass in each of `bins` annuli of width `bin_width` around `center`
+// (in the x/y plane), skipping `skip`; anything beyond the last ring is dropped
+fn annulus_masses(particles: &[Particle], center: Point3<f32>, bin_width: f32, bins: usize, skip: Option<usize>) -> Vec<f32> {
+    let mut masses = vec![0.0; bins];
+    for (i, particle) in particles.iter().enumerate() {
+        if Some(i) == skip {
+            continue;
+        }
+        let dx = particle.position.x - center.x;
+        let dy = particle.position.y - center.y;
+        let bin = ((dx * dx + dy * dy).sqrt() / bin_width) as usize;
+        if let Some(mass) = masses.get_mut(bin) {
+            *mass += particle.mass;
+        }
+    }
+    masses
+}
+
+// Mass-weighted mean velocity of the particles in each `cell`-sized square of
+// the x/y plane, skipping `skip`, as (cell center, mean velocity). Cells with
+// no mass are left out.
+fn mean_velocity_grid(particles: &[Particle], cell: f32, skip: Option<usize>) -> Vec<(Point3<f32>, Vector3<f32>)> {
+    let mut cells: HashMap<(i32, i32), (f32, Vector3<f32>)> = HashMap::new();
+    for (i, particle) in particles.iter().enumerate() {
+        if Some(i) == skip || particle.mass <= 0.0 {
+            continue;
+        }
+        let key = ((particle.position.x / cell).floor() as i32, (particle.position.y / cell).floor() as i32);
+        let (mass, momentum) = cells.entry(key).or_insert((0.0, Vector3 { x: 0.0, y: 0.0, z: 0.0 }));
+        *mass += particle.mass;
+        momentum.x += particle.mass * particle.velocity.x;
+        momentum.y += particle.mass * particle.velocity.y;
+        momentum.z += particle.mass * particle.velocity.z;
+    }
+    cells
+        .into_iter()
+        .map(|((x, y), (mass, momentum))| {
+            let center = Point3 { x: (x as f32 + 0.5) * cell, y: (y as f32 + 0.5) * cell, z: 0.0 };
+            (center, Vector3 { x: momentum.x / mass, y: momentum.y / mass, z: momentum.z / mass })
+        })
+        .collect()
+}
+
+// Mass per unit area of each annulus from annulus_masses
+fn surface_density(masses: &[f32], bin_width: f32) -> Vec<f32> {
+    masses
+        .iter()
+        .enumerate()
+        .map(|(i, mass)| {
+            let (inner, outer) = (i as f32 * bin_width, (i + 1) as f32 * bin_width);
+            mass / (PI * (outer * outer - inner * inner))
+        })
+        .collect()
+}
+
+// Totals exact physics would hold constant, for judging integrator drift
+#[derive(Clone, Copy)]
+struct Conserved {
+    energy: f32,
+    momentum: Vector3<f32>,
+    angular_momentum: f32,
+    // Sum of every |m·v|; the total momentum is often near zero, so its
+    // drift is measured against this instead
+    momentum_scale: f32,
+}
+
+impl Conserved {
+    fn measure(particles: &[Particle]) -> Self {
+        Conserved {
+            energy: kinetic_energy(particles) + potential_energy(particles),
+            momentum: total_momentum(particles),
+            angular_momentum: total_angular_momentum_z(particles),
+            momentum_scale: particles
+                .iter()
+                .map(|p| p.mass * (p.velocity.x * p.velocity.x + p.velocity.y * p.velocity.y + p.velocity.z * p.velocity.z).sqrt())
+                .sum(),
+        }
+    }
+
+    // Percentage drift of (energy, momentum, angular momentum) from `self` to `now`
+    fn drift(&self, now: &Conserved) -> (f32, f32, f32) {
+        let dp = Vector3 {
+            x: now.momentum.x - self.momentum.x,
+            y: now.momentum.y - self.momentum.y,
+            z: now.momentum.z - self.momentum.z,
+        };
+        let dp = (dp.x * dp.x + dp.y * dp.y + dp.z * dp.z).sqrt();
+        (
+            percent_drift(now.energy - self.energy, self.energy.abs()),
+            percent_drift(dp, self.momentum_scale),
+            percent_drift(now.angular_momentum - self.angular_momentum, self.angular_momentum.abs()),
+        )
+    }
+}
+
+// Rough description of what the run is doing, for the HUD
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Phase {
+    Collapsing,
+    Accreting,
+    Relaxed,
+}
+
+impl Phase {
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Collapsing => "Collapsing",
+            Phase::Accreting => "Accreting",
+            Phase::Relaxed => "Relaxed",
+        }
+    }
+}
+
+// Labels the run from its recent merge rate (merges per 100 steps), its
+// energy drift since reset in percent, and the fraction of the orbiting mass
+// held by its heaviest body. Rapid merging while the mass is still spread out, or a
+// large energy error from violent close encounters, reads as a collapse;
+// steady merging as accretion; little of either as relaxed.
+fn classify_phase(merges_per_100: f32, energy_drift: f32, concentration: f32) -> Phase {
+    if energy_drift.abs() > 10.0 || (merges_per_100 > 5.0 && concentration < 0.5) {
+        Phase::Collapsing
+    } else if merges_per_100 > 0.5 {
+        Phase::Accreting
+    } else {
+        Phase::Relaxed
+    }
+}
+
+// `change` as a percentage of `scale`, or 0 when there's nothing to compare to
+fn percent_drift(change: f32, scale: f32) -> f32 {
+    if scale > f32::EPSILON {
+        100.0 * change / scale
+    } else {
+        0.0
+    }
+}
+
+// One line of the aggregate statistics log, matching STATS_HEADER
+fn stats_row(step: u64, time: f32, particles: &[Particle]) -> String {
+    let kinetic = kinetic_energy(particles);
+    let potential = potential_energy(particles);
+    let momentum = total_momentum(particles);
+    let max_mass = particles.iter().map(|p| p.mass).fold(0.0, f32::max);
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{}",
+        step,
+        time,
+        kinetic,
+        potential,
+        kinetic + potential,
+        momentum.x,
+        momentum.y,
+        momentum.z,
+        total_angular_momentum_z(particles),
+        particles.len(),
+        max_mass
+    )
+}
+
+// Fully saturated color at `hue` in [0, 1) around the color wheel
+fn hue_color(hue: f32) -> Color {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    Color::new(r, g, b, 1.0)
+}
+
+// Rainbow from red at the inner edge of the spawn ring to violet at the outer
+fn birth_ring_color(distance: f32) -> Color {
+    let t = ((distance - SPAWN_MIN_RADIUS) / (SPAWN_MAX_RADIUS - SPAWN_MIN_RADIUS)).clamp(0.0, 1.0);
+    hue_color(t * 0.8)
+}
+
+// True once a run that started with enough bodies to matter is down to
+// only a handful besides the star, usually because everything fell in
+fn has_collapsed(orbiting: usize, initial: usize) -> bool {
+    initial >= COLLAPSE_MIN_SURVIVORS && orbiting < COLLAPSE_MIN_SURVIVORS
+}
+
+// Gap between a selected particle and its focus ring, `elapsed` seconds after
+// it was selected. Starts wide and closes in, then pulses between the bounds.
+fn focus_ring_gap(elapsed: f32) -> f32 {
+    let phase = 0.5 - 0.5 * (elapsed / FOCUS_RING_PERIOD * 2.0 * PI).cos();
+    let pulse = FOCUS_RING_MIN + (FOCUS_RING_MAX - FOCUS_RING_MIN) * phase;
+    let intro = (1.0 - elapsed / FOCUS_RING_INTRO).max(0.0);
+    pulse + intro * intro * 40.0
+}
+
+// Union-find root of `i`, halving the path on the way up
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+// Two bodies are a bound pair when they're close and their two-body energy
+// (relative kinetic energy minus mutual potential) is negative
+fn is_bound_pair(a: &Particle, b: &Particle) -> bool {
+    let dx = b.position.x - a.position.x;
+    let dy = b.position.y - a.position.y;
+    let dz = b.position.z - a.position.z;
+    let separation = (dx * dx + dy * dy + dz * dz).sqrt();
+    if separation > BINARY_MAX_SEPARATION || separation <= f32::EPSILON {
+        return false;
+    }
+    let dvx = b.velocity.x - a.velocity.x;
+    let dvy = b.velocity.y - a.velocity.y;
+    let dvz = b.velocity.z - a.velocity.z;
+    let reduced_mass = a.mass * b.mass / (a.mass + b.mass);
+    let kinetic = 0.5 * reduced_mass * (dvx * dvx + dvy * dvy + dvz * dvz);
+    let potential = -G * a.mass * b.mass / separation;
+    kinetic + potential < 0.0
+}
+
+// Groups particles linked by bound pairs, ignoring `skip` (the star, which
+// everything is bound to). Only groups of two or more are returned.
+fn find_bound_groups(particles: &[Particle], skip: usize) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..particles.len()).collect();
+    for i in 0..particles.len() {
+        for j in (i + 1)..particles.len() {
+            if i == skip || j == skip || !is_bound_pair(&particles[i], &particles[j]) {
+                continue;
+            }
+            let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+            parents[a] = b;
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of_root = vec![usize::MAX; particles.len()];
+    for i in 0..particles.len() {
+        let r = root(&mut parents, i);
+        if group_of_root[r] == usize::MAX {
+            group_of_root[r] = groups.len();
+            groups.push(Vec::new());
+        }
+        groups[group_of_root[r]].push(i);
+    }
+    groups.retain(|group| group.len() >= 2);
+    groups
+}
+
+// Friends-of-friends clustering: particles closer than `linking_length` are
+// friends, and a cluster is everything reachable through friends. Returns a
+// label per particle, numbered from 0 in order of each cluster's lowest
+// index; particles in groups smaller than CLUSTER_MIN_MEMBERS, and `skip`,
+// get None.
+fn friends_of_friends(particles: &[Particle], linking_length: f32, skip: Option<usize>) -> Vec<Option<usize>> {
+    let mut parents: Vec<usize> = (0..particles.len()).collect();
+    let reach = linking_length * linking_length;
+    for i in 0..particles.len() {
+        for j in (i + 1)..particles.len() {
+            if Some(i) == skip || Some(j) == skip || particles[i].tracer || particles[j].tracer {
+                continue;
+            }
+            let (a, b) = (&particles[i].position, &particles[j].position);
+            let (dx, dy, dz) = (b.x - a.x, b.y - a.y, b.z - a.z);
+            if dx * dx + dy * dy + dz * dz <= reach {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a] = b;
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..particles.len()).map(|i| root(&mut parents, i)).collect();
+    let mut members = vec![0; particles.len()];
+    for &r in &roots {
+        members[r] += 1;
+    }
+    let mut label_of_root = vec![None; particles.len()];
+    let mut next = 0;
+    roots
+        .iter()
+        .map(|&r| {
+            if members[r] < CLUSTER_MIN_MEMBERS {
+                return None;
+            }
+            Some(*label_of_root[r].get_or_insert_with(|| {
+                next += 1;
+                next - 1
+            }))
+        })
+        .collect()
+}
+
+// Scales `vector` down to length `max` if it's longer, keeping its direction.
+// Used to cap speeds and accelerations: non-physical, but keeps runaway
+// encounters on screen.
+fn clamp_magnitude(vector: &mut Vector3<f32>, max: f32) {
+    let length = (vector.x * vector.x + vector.y * vector.y + vector.z * vector.z).sqrt();
+    if length > max && length > 0.0 {
+        let scale = max / length;
+        vector.x *= scale;
+        vector.y *= scale;
+        vector.z *= scale;
+    }
+}
+
+// A problem with a particle CSV, pointing at the offending line (1-based)
+#[derive(Debug)]
+struct CsvError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// `#rrggbb`, ignoring alpha
+fn color_to_hex(color: Color) -> String {
+    let (r, g, b) = color.to_rgb();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn color_from_hex(hex: &str) -> Option<Color> {
+    let digits = hex.strip_prefix('#')?;
+    if digits.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(digits, 16).ok()?;
+    Some(Color::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+// Writes particles in the format parse_particle_csv reads back, colors included
+fn particles_to_csv(particles: &[Particle]) -> String {
+    let mut csv = String::from("x,y,vx,vy,mass,color\n");
+    for p in particles {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            p.position.x,
+            p.position.y,
+            p.velocity.x,
+            p.velocity.y,
+            p.mass,
+            color_to_hex(p.birth_color)
+        ));
+    }
+    csv
+}
+
+// Parses particles from CSV with the header `x,y,vx,vy,mass`, optionally
+// followed by a `color` column of `#rrggbb` birth colors. Blank lines are
+// skipped; anything else malformed is reported with its line number.
+// The ephemeris header `x_au,y_au,vx_kms,vy_kms,mass_msun` marks real data,
+// converted through `units` so G = 1 still holds, with the origin (the Sun,
+// for heliocentric data) placed at the window center.
+fn parse_particle_csv(contents: &str, units: &Units) -> Result<Vec<Particle>, CsvError> {
+    const SIM_HEADER: [&str; 5] = ["x", "y", "vx", "vy", "mass"];
+    const EPHEMERIS_HEADER: [&str; 5] = ["x_au", "y_au", "vx_kms", "vy_kms", "mass_msun"];
+
+    let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let Some((header_index, header)) = lines.next() else {
+        return Err(CsvError { line: 1, message: "file is empty".to_string() });
+    };
+    let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+    let has_color = columns.len() == SIM_HEADER.len() + 1 && columns.last() == Some(&"color");
+    let names = &columns[..columns.len() - usize::from(has_color)];
+    let ephemeris = names == EPHEMERIS_HEADER;
+    if names != SIM_HEADER && !ephemeris {
+        return Err(CsvError {
+            line: header_index + 1,
+            message: format!(
+                "expected header `{}` or `{}` (plus an optional color), found `{}`",
+                SIM_HEADER.join(","),
+                EPHEMERIS_HEADER.join(","),
+                header.trim()
+            ),
+        });
+    }
+    let header = if ephemeris { EPHEMERIS_HEADER } else { SIM_HEADER };
+
+    let mut particles = Vec::new();
+    for (index, line) in lines {
+        let line_number = index + 1;
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != columns.len() {
+            return Err(CsvError {
+                line: line_number,
+                message: format!("expected {} fields, found {}", columns.len(), fields.len()),
+            });
+        }
+        let mut values = [0.0f32; 5];
+        for (value, (field, name)) in values.iter_mut().zip(fields.iter().zip(header)) {
+            *value = field.parse().map_err(|_| CsvError {
+                line: line_number,
+                message: format!("invalid {} value `{}`", name, field),
+            })?;
+        }
+        let [mut x, mut y, mut vx, mut vy, mut mass] = values;
+        if mass <= 0.0 {
+            return Err(CsvError { line: line_number, message: format!("mass must be positive, found {}", mass) });
+        }
+        if ephemeris {
+            let speed_scale = units.time_scale / (KM_S_PER_AU_YR * units.distance_scale);
+            x = x / units.distance_scale + WINDOW_CENTER.x;
+            y = y / units.distance_scale + WINDOW_CENTER.y;
+            vx *= speed_scale;
+            vy *= speed_scale;
+            mass /= units.mass_scale();
+        }
+        let mut particle = Particle::new(x, y, 0.0, mass);
+        particle.velocity = Vector3 { x: vx, y: vy, z: 0.0 };
+        if has_color {
+            let field = fields[header.len()];
+            particle.birth_color = color_from_hex(field).ok_or_else(|| CsvError {
+                line: line_number,
+                message: format!("invalid color `{}`, expected #rrggbb", field),
+            })?;
+        }
+        particles.push(particle);
+    }
+    Ok(particles)
+}
+
+// Something a script does to the simulation
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScriptAction {
+    // A new body at (x, y) moving at (vx, vy)
+    AddMass { x: f32, y: f32, vx: f32, vy: f32, mass: f32 },
+    // Pushes everything within `radius` of (x, y) outward, by up to `strength`
+    // at the center and fading to nothing at the edge
+    Explode { x: f32, y: f32, strength: f32, radius: f32 },
+}
+
+// A scripted action and the step it fires after
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScriptEvent {
+    step: u64,
+    action: ScriptAction,
+}
+
+// A problem with an event script, pointing at the offending line (1-based)
+#[derive(Debug)]
+struct ScriptError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// Parses an event script, one event per line:
+//   <step> add <x> <y> <vx> <vy> <mass>
+//   <step> explode <x> <y> <strength> <radius>
+// Anything after a # is a comment, and blank lines are skipped.
+fn parse_script(contents: &str) -> Result<Vec<ScriptEvent>, ScriptError> {
+    let mut events = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| ScriptError { line: index + 1, message };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let step = words[0].parse::<u64>().map_err(|_| error(format!("invalid step `{}`", words[0])))?;
+        let Some(&kind) = words.get(1) else {
+            return Err(error("missing event after the step".to_string()));
+        };
+        let numbers = words[2..]
+            .iter()
+            .map(|word| word.parse::<f32>().map_err(|_| error(format!("invalid number `{}`", word))))
+            .collect::<Result<Vec<f32>, ScriptError>>()?;
+        let action = match (kind, numbers.as_slice()) {
+            ("add", &[x, y, vx, vy, mass]) if mass > 0.0 => ScriptAction::AddMass { x, y, vx, vy, mass },
+            ("add", &[_, _, _, _, mass]) => return Err(error(format!("mass must be positive, found {}", mass))),
+            ("add", _) => return Err(error("expected `add <x> <y> <vx> <vy> <mass>`".to_string())),
+            ("explode", &[x, y, strength, radius]) if radius > 0.0 => ScriptAction::Explode { x, y, strength, radius },
+            ("explode", _) => return Err(error("expected `explode <x> <y> <strength> <radius>` with a positive radius".to_string())),
+            _ => return Err(error(format!("unknown event `{}`", kind))),
+        };
+        events.push(ScriptEvent { step, action });
+    }
+    Ok(events)
+}
+
+fn mass_label_indices(particles: &[Particle], threshold: f32) -> Vec<usize> {
+    particles
+        .iter()
+        .enumerate()
+        .filter(|(_, particle)| particle.mass > threshold)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// Difference in the star's pull between the near and far side of a body of
+// radius `body_radius` at distance `separation`. None when the body reaches
+// into the star's center, where the difference is meaningless.
+fn tidal_acceleration(star_mass: f32, separation: f32, body_radius: f32) -> Option<f32> {
+    if separation <= body_radius {
+        return None;
+    }
+    let near = separation - body_radius;
+    let far = separation + body_radius;
+    Some(G * star_mass * (1.0 / (near * near) - 1.0 / (far * far)))
+}
+
+// Real-world scale for HUD readouts. Physics always runs in simulation
+// units; this only converts what's displayed.
+#[derive(Clone, Copy)]
+struct Units {
+    distance_name: &'static str,
+    time_name: &'static str,
+    // Real distance per simulation length unit, and real time per sim time unit
+    distance_scale: f32,
+    time_scale: f32,
+}
+
+impl Units {
+    const SIMULATION: Units = Units { distance_name: "u", time_name: "t", distance_scale: 1.0, time_scale: 1.0 };
+
+    fn astronomical(au_per_unit: f32, years_per_time: f32) -> Units {
+        Units { distance_name: "AU", time_name: "yr", distance_scale: au_per_unit, time_scale: years_per_time }
+    }
+
+    fn distance(&self, d: f32) -> f32 {
+        d * self.distance_scale
+    }
+
+    fn time(&self, t: f32) -> f32 {
+        t * self.time_scale
+    }
+
+    fn speed(&self, v: f32) -> f32 {
+        v * self.distance_scale / self.time_scale
+    }
+
+    // Solar masses per simulation mass unit: the one that makes G = 1 at
+    // this distance and time scale, since G is 4π² AU³/(M☉ yr²)
+    fn mass_scale(&self) -> f32 {
+        self.distance_scale.powi(3) / (4.0 * PI * PI * self.time_scale * self.time_scale)
+    }
+
+    fn speed_name(&self) -> String {
+        format!("{}/{}", self.distance_name, self.time_name)
+    }
+}
+
+// Renders the particle setup as Rust source that rebuilds it, for turning
+// a hand-built configuration into a preset
+fn particles_to_rust(particles: &[Particle]) -> String {
+    let mut code = String::from("let mut particles = Vec::new();\n");
+    for particle in particles {
+        code.push_str(&format!(
+            "let mut particle = Particle::new({:.2}, {:.2}, {:.2}, {:.3});\n",
+            particle.position.x, particle.position.y, particle.position.z, particle.mass
+        ));
+        code.push_str(&format!(
+            "particle.velocity = Vector3 {{ x: {:.4}, y: {:.4}, z: {:.4} }};\n",
+            particle.velocity.x, particle.velocity.y, particle.velocity.z
+        ));
+        code.push_str("particles.push(particle);\n");
+    }
+    code
+}
+
+struct SimulationState {
+    particles: Vec<Particle>,
+    particle_count: usize,
+    initial_mass_range: (f32, f32),
+    initial_velocity_multiplier: f32,
+    paused: bool,
+    zoom: f32,
+    pan: Point2<f32>,
+    buttons: Vec<Button>,
+    sliders: Vec<Slider>,
+    is_panning: bool,
+    dragging_slider: Option<usize>,
+    // Multiplies control sizes, fonts, and hit regions
+    ui_scale: f32,
+    last_mouse_pos: Point2<f32>,
+    // Where reset places the central star
+    star_home: Point2<f32>,
+    adding_mass: bool,
+    mass_preview: Option<Point2<f32>>,
+    orbit_builder: OrbitBuilder,
+    // Apply particle-count changes immediately instead of waiting for a reset
+    live_particle_count: bool,
+    selected: Option<usize>,
+    // Only draw the selected particle and its dominant attractor
+    isolate: bool,
+    merge_effects: Vec<MergeEffect>,
+    // Drawn star radius, eased toward the physical radius as it accretes
+    star_display_radius: f32,
+    // Unit circle reused for every particle and drawn instanced
+    particle_mesh: Option<Mesh>,
+    particle_instances: Option<graphics::InstanceArray>,
+    starfield: Vec<BackgroundStar>,
+    show_starfield: bool,
+    // Integration steps and accumulated simulation time since the last reset
+    steps: u64,
+    sim_time: f32,
+    quadtree_overlay: QuadtreeOverlay,
+    // Pseudo-3D tilted view of the 2D disk (drawing only)
+    tilted: bool,
+    show_binaries: bool,
+    // Bound groups from the last periodic scan
+    bound_groups: Vec<Vec<usize>>,
+    // Friends-of-friends cluster label per particle from the last scan,
+    // with the cluster count and the heaviest cluster's mass
+    show_clusters: bool,
+    cluster_labels: Vec<Option<usize>>,
+    cluster_summary: (usize, f32),
+    show_contours: bool,
+    show_mass_labels: bool,
+    show_tidal: bool,
+    show_orbits: bool,
+    show_bounds: bool,
+    // Shakes so far; each one seeds from SHAKE_SEED plus this, so a run of
+    // shakes is reproducible but no two in a row are identical
+    shakes: u64,
+    // Panel listing particles by mass; the ranking is only re-sorted every
+    // MASS_LIST_REFRESH frames, or when the particle count changes
+    show_mass_list: bool,
+    mass_ranking: Vec<usize>,
+    mass_ranking_age: u32,
+    mass_list_scroll: usize,
+    // Reference region of the world, drawn as a faint frame; the window by default
+    world_bounds: graphics::Rect,
+    boundary_mode: BoundaryMode,
+    // Physics steps per rendered frame; above 1 fast-forwards
+    frame_skip: u32,
+    // Frames advanced so far, counted apart from physics steps
+    rendered_frames: u64,
+    // While on, every click in the scene drops a tracer
+    adding_tracers: bool,
+    gravity_enabled: bool,
+    lock_dt_power_of_two: bool,
+    radius_model: RadiusModel,
+    show_birth_colors: bool,
+    show_timings: bool,
+    force_timing: PhaseTiming,
+    collision_timing: PhaseTiming,
+    render_timing: PhaseTiming,
+    // Set by the step loop when nearly everything has merged into the star.
+    // Measured against how many bodies the run started with.
+    collapsed: bool,
+    initial_orbiting: usize,
+    // Central stars the current run was built with, which don't count as orbiting
+    star_count: usize,
+    period_tracker: Option<PeriodTracker>,
+    // Merges from the latest step, waiting for the frontend to react
+    collision_events: Vec<CollisionEvent>,
+    force_mode: ForceMode,
+    // Brute-force particle count above which the HUD suggests Barnes-Hut
+    safe_particles: usize,
+    camera_flight: Option<CameraFlight>,
+    // Saved views, slot 1 first; kept in BOOKMARKS_FILE between runs
+    bookmarks: [Option<CameraBookmark>; BOOKMARK_SLOTS],
+    show_star_force: bool,
+    // Render between fixed-rate physics ticks instead of stepping every frame
+    smooth_motion: bool,
+    scenario: Scenario,
+    // Merges and accretions since the last reset
+    merges: u64,
+    // (steps, merges) at the start of the current phase window
+    phase_mark: (u64, u64),
+    phase: Option<Phase>,
+    // Baseline for the conservation drift readout, taken at every reset
+    conserved_at_reset: Conserved,
+    show_drift: bool,
+    // Escape speed at the cursor from the body pulling hardest there,
+    // refreshed as the mouse moves
+    show_cursor_escape: bool,
+    cursor_escape: Option<(usize, f32)>,
+    // Wheel zoom multiplier per notch, set with --zoom-step
+    zoom_step: f32,
+    // Zoom by the wheel's actual delta (smooth on trackpads) instead of per notch
+    smooth_zoom: bool,
+    // Circle smoothness for particles and UI; changing it rebuilds the particle mesh
+    render_quality: RenderQuality,
+    show_hill_spheres: bool,
+    // While the right button is held: the cursor's screen position, and +1
+    // to attract or -1 to repel
+    gun: Option<(Point2<f32>, f32)>,
+    // Rotation of the 2D view about the window center, in radians
+    view_angle: f32,
+    // Keeps the view at zero rotation, ignoring [ and ]
+    north_up: bool,
+    show_compass: bool,
+    // Draw fast particles as streaks from their previous position
+    motion_blur: bool,
+    // Events from --script, replayed on every run as the step counter reaches them
+    script: Vec<ScriptEvent>,
+    // Run the physics in f64 (direct sum, every step), rounding to f32 for display
+    double_precision: bool,
+    // Seed of the disk reset last built; a frozen seed is reused by every reset
+    seed: u64,
+    freeze_seed: bool,
+    // Soften forces by body size instead of one global term
+    size_softening: bool,
+    // Positions and radii from a snapshot, drawn but never simulated
+    ghost: Option<Vec<(Point3<f32>, f32)>>,
+    // Record and draw the path of whichever particle is heaviest
+    track_leader: bool,
+    leader: Option<usize>,
+    leader_id: Option<u64>,
+    // None marks where the lead passed to a different body
+    leader_path: VecDeque<Option<Point3<f32>>>,
+    // Displace the starfield around heavy bodies, a lensing look-alike
+    show_lensing: bool,
+    // Azimuthally averaged surface density by annulus, refreshed every
+    // DENSITY_REFRESH frames while shown
+    show_density_profile: bool,
+    density_profile: Vec<f32>,
+    density_profile_age: u32,
+    // Mean velocity per FLOW_CELL square, rebuilt every FLOW_REFRESH frames
+    // while shown
+    show_flow: bool,
+    flow_field: Vec<(Point3<f32>, Vector3<f32>)>,
+    flow_field_age: u32,
+    // Expired particles respawn at the disk's edge instead of vanishing
+    recycle_expired: bool,
+    // Clicks behind the slider panel go to the panel, never the world
+    panel_blocks_clicks: bool,
+    // Wall-clock time since the last smooth-motion tick
+    physics_clock: f32,
+    // Slider whose text box receives typed digits; clicking a box moves it
+    focused_input: usize,
+    // View everything from the selected particle's rest frame
+    rest_frame: bool,
+    // Seconds of cosmetic animation, advancing even while paused
+    anim_time: f32,
+    // Which particle the focus ring is on and when (in anim_time) it got there
+    focus: Option<(usize, f32)>,
+    // Taken automatically every time the simulation is paused
+    pause_snapshot: Option<Snapshot>,
+    // What the last clear-visuals press removed, until it's undone
+    cleared_visuals: Option<Visuals>,
+    // Readouts toggle between simulation units and the real-world scale
+    real_units: Units,
+    use_real_units: bool,
+    star_mass_history: History,
+    show_accretion_plot: bool,
+    // Iso-potential segments in world coordinates, cached so a paused view
+    // isn't recomputed every frame
+    contour_cache: Option<(ContourKey, Vec<[Point2<f32>; 2]>)>,
+    // Where O-key imports read particles from; set with --load
+    csv_path: String,
+    // Open while aggregate statistics are being logged, one row per step
+    stats_log: Option<BufWriter<File>>,
+    // Frame recording: every rendered frame is written as a numbered PNG
+    recording: bool,
+    recorded_frames: u32,
+    frames_dir: String,
+    // 3d stuff
+    is_3d: bool,
+    rotation_x: f32,
+    rotation_y: f32,
+    
+}
+
+
+impl SimulationState {
+    fn new_particle(&self, x: f32, y: f32, z: f32, mass: f32) -> Particle {
+        let mut particle = Particle::new(x, y, z, mass);
+        particle.radius = self.radius_model.radius(mass);
+        particle
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            particles: self.particles.clone(),
+            steps: self.steps,
+            sim_time: self.sim_time,
+            star_mass_history: self.star_mass_history.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.particles = snapshot.particles.clone();
+        self.steps = snapshot.steps;
+        self.sim_time = snapshot.sim_time;
+        self.star_mass_history = snapshot.star_mass_history.clone();
+        self.selected = None;
+        self.bound_groups.clear();
+        self.merge_effects.clear();
+        if let Some(star) = self.star_index() {
+            self.star_display_radius = self.particles[star].radius;
+        }
+    }
+
+    // Empties trails, merge ripples and plot histories without touching the
+    // physics; trails keep recording from here
+    fn clear_visuals(&mut self) {
+        let trails = self
+            .particles
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, particle)| particle.trail.as_mut().map(|trail| (index, std::mem::take(trail))))
+            .collect();
+        self.cleared_visuals = Some(Visuals {
+            trails,
+            merge_effects: std::mem::take(&mut self.merge_effects),
+            star_mass_history: std::mem::replace(&mut self.star_mass_history, History::new()),
+            leader_path: std::mem::take(&mut self.leader_path),
+        });
+    }
+
+    // Puts back what the last clear removed. Anything drawn since is
+    // dropped, and trails only return to particles still tracked at the
+    // same index
+    fn undo_clear_visuals(&mut self) {
+        let Some(visuals) = self.cleared_visuals.take() else {
+            return;
+        };
+        for (index, trail) in visuals.trails {
+            if let Some(slot) = self.particles.get_mut(index).and_then(|particle| particle.trail.as_mut()) {
+                *slot = trail;
+            }
+        }
+        self.merge_effects = visuals.merge_effects;
+        self.star_mass_history = visuals.star_mass_history;
+        self.leader_path = visuals.leader_path;
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if self.paused {
+            self.pause_snapshot = Some(self.snapshot());
+        }
+    }
+
+    // Jumps back to the moment of the last pause and stays paused there
+    fn restore_last_pause(&mut self) {
+        if let Some(snapshot) = self.pause_snapshot.take() {
+            self.restore(&snapshot);
+            self.pause_snapshot = Some(snapshot);
+            self.paused = true;
+        }
+    }
+
+    fn toggle_radius_model(&mut self) {
+        self.radius_model = match self.radius_model {
+            RadiusModel::ConstantDensity => RadiusModel::Fixed(FIXED_PARTICLE_RADIUS),
+            RadiusModel::Fixed(_) => RadiusModel::ConstantDensity,
+        };
+        for particle in &mut self.particles {
+            particle.radius = self.radius_model.radius(particle.mass);
+        }
+        if let Some(star) = self.star_index() {
+            self.star_display_radius = self.particles[star].radius;
+        }
+    }
+
+    fn add_large_mass(&mut self, x: f32, y: f32) {
+        let mass = self.sliders[3].value * 100.0;
+        let mut particle = self.new_particle(x, y, 0.0, mass);
+        particle.user_placed = true;
+        self.particles.push(particle);
+    }
+
+    fn new() -> Self {
+        let mut state = SimulationState {
+            particles: Vec::new(),
+            particle_count: 100,
+            initial_mass_range: (1.0, 5.0),
+            initial_velocity_multiplier: 1.0,
+            paused: true,
+            zoom: 1.0,
+            pan: Point2 { x: 0.0, y: 0.0 },
+            is_3d: false,
+            rotation_x: 0.0,
+            rotation_y: 0.0,
+            buttons: vec![
+                Button::new(10.0, 10.0, 100.0, 30.0, "Run/Pause"),
+                Button::new(120.0, 10.0, 100.0, 30.0, "Reset"),
+                Button::new(230.0, 10.0, 100.0, 30.0, "Add Mass"),
+                Button::new(340.0, 10.0, 100.0, 30.0, "2D/3D"),
+                Button::new(450.0, 10.0, 100.0, 30.0, "Orbit"),
+                Button::new(560.0, 10.0, 100.0, 30.0, "Radius"),
+                Button::new(670.0, 10.0, 100.0, 30.0, "Shake"),
+                Button::new(780.0, 10.0, 100.0, 30.0, "Disk/Cloud"),
+                Button::new(890.0, 10.0, 100.0, 30.0, "Soft Reset"),
+            ],
+            sliders: vec![
+                Slider::new(1.0, 0.1, 10.0, "Time Speed", 50.0, false),
+                Slider::new(100.0, 10.0, 1000.0, "Particles", 90.0, true).with_step(1.0),
+                Slider::new(1.0, 0.1, 5.0, "Velocity", 130.0, false),
+                Slider::new(3.0, 0.1, 100.0, "Mass", 170.0, false),
+                Slider::new(1.0, 0.1, 10.0, "Softening", 210.0, false),
+                Slider::new(DT, 0.001, 0.1, "Time Step", 250.0, false),
+                Slider::new(1000.0, 100.0, 5000.0, "Central Mass", 290.0, false),
+                Slider::new(1.0, 1.0, 10.0, "Force Every", 330.0, false).with_step(1.0),
+                Slider::new(5.0, 0.0, 50.0, "Kick Speed", 370.0, false),
+                Slider::new(0.0, 0.0, 360.0, "Kick Angle", 410.0, false),
+                Slider::new(0.0, 0.0, 500.0, "Max Speed", 450.0, false),
+                Slider::new(2.0, 1.0, 3.0, "Force Exponent", 490.0, false),
+                Slider::new(0.0, 0.0, 0.9, "Eccentricity", 530.0, false),
+                Slider::new(0.0, 0.0, 0.5, "Drag", 570.0, false),
+                Slider::new(0.0, 0.0, 200.0, "Merge Speed", 610.0, false),
+                Slider::new(0.0, 0.0, 2000.0, "Max Accel", 650.0, false),
+                Slider::new(1.0, 0.0, 10.0, "Shake Sigma", 690.0, false),
+                Slider::new(0.0, 0.0, 1.0, "Retrograde", 730.0, false),
+                Slider::new(0.0, 0.0, 2000.0, "Cutoff", 770.0, false),
+                Slider::new(0.0, 0.0, 100000.0, "Stop At Step", 810.0, true).with_step(1.0),
+                Slider::new(1.0, 1.0, 4.0, "Central Stars", 850.0, false).with_step(1.0),
+                Slider::new(0.0, 0.0, 10000.0, "Lifetime", 890.0, false).with_step(1.0),
+                Slider::new(1.0, 0.25, 4.0, "Cross Section", 930.0, false),
+                Slider::new(0.0, 0.0, 1.0, "Focusing", 970.0, false),
+                Slider::new(1000.0, 0.0, 5000.0, "Gun Strength", 1010.0, false),
+            ],
+            is_panning: false,
+            dragging_slider: None,
+            ui_scale: 1.0,
+            last_mouse_pos: Point2 { x: 0.0, y: 0.0 },
+            star_home: Point2 { x: WINDOW_WIDTH / 2.0, y: WINDOW_HEIGHT / 2.0 },
+            adding_mass: false,
+            mass_preview: None,
+            orbit_builder: OrbitBuilder::Off,
+            live_particle_count: false,
+            selected: None,
+            isolate: false,
+            merge_effects: Vec::new(),
+            star_display_radius: 0.0,
+            particle_mesh: None,
+            particle_instances: None,
+            starfield: generate_starfield(STARFIELD_SEED, STARFIELD_COUNT),
+            show_starfield: false,
+            steps: 0,
+            sim_time: 0.0,
+            quadtree_overlay: QuadtreeOverlay::Off,
+            tilted: false,
+            show_binaries: false,
+            bound_groups: Vec::new(),
+            show_clusters: false,
+            cluster_labels: Vec::new(),
+            cluster_summary: (0, 0.0),
+            show_contours: false,
+            show_mass_labels: false,
+            show_tidal: false,
+            show_orbits: false,
+            show_bounds: false,
+            adding_tracers: false,
+            shakes: 0,
+            show_mass_list: false,
+            mass_ranking: Vec::new(),
+            mass_ranking_age: 0,
+            mass_list_scroll: 0,
+            world_bounds: graphics::Rect::new(0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT),
+            boundary_mode: BoundaryMode::Open,
+            frame_skip: 1,
+            rendered_frames: 0,
+            gravity_enabled: true,
+            lock_dt_power_of_two: false,
+            radius_model: RadiusModel::ConstantDensity,
+            show_birth_colors: false,
+            show_timings: false,
+            force_timing: PhaseTiming::default(),
+            collision_timing: PhaseTiming::default(),
+            render_timing: PhaseTiming::default(),
+            pause_snapshot: None,
+            cleared_visuals: None,
+            force_mode: ForceMode::BruteForce,
+            safe_particles: DEFAULT_SAFE_PARTICLES,
+            camera_flight: None,
+            bookmarks: std::fs::read_to_string(BOOKMARKS_FILE).map_or([None; BOOKMARK_SLOTS], |contents| parse_bookmarks(&contents)),
+            rest_frame: false,
+            show_star_force: false,
+            smooth_motion: false,
+            scenario: Scenario::AccretionDisk,
+            panel_blocks_clicks: true,
+            recycle_expired: false,
+            show_density_profile: false,
+            show_lensing: false,
+            show_cursor_escape: false,
+            cursor_escape: None,
+            zoom_step: DEFAULT_ZOOM_STEP,
+            smooth_zoom: false,
+            render_quality: RenderQuality::Medium,
+            ghost: None,
+            size_softening: false,
+            show_hill_spheres: false,
+            motion_blur: false,
+            gun: None,
+            view_angle: 0.0,
+            north_up: false,
+            show_compass: false,
+            script: Vec::new(),
+            double_precision: false,
+            seed: 0,
+            freeze_seed: false,
+            track_leader: false,
+            leader: None,
+            leader_id: None,
+            leader_path: VecDeque::new(),
+            density_profile: Vec::new(),
+            density_profile_age: 0,
+            show_flow: false,
+            flow_field: Vec::new(),
+            flow_field_age: 0,
+            merges: 0,
+            phase_mark: (0, 0),
+            phase: None,
+            conserved_at_reset: Conserved::measure(&[]),
+            show_drift: false,
+            physics_clock: 0.0,
+            focused_input: 1,
+            anim_time: 0.0,
+            focus: None,
+            collision_events: Vec::new(),
+            period_tracker: None,
+            collapsed: false,
+            initial_orbiting: 0,
+            star_count: 1,
+            real_units: Units::astronomical(0.01, 0.01),
+            use_real_units: false,
+            star_mass_history: History::new(),
+            show_accretion_plot: false,
+            contour_cache: None,
+            csv_path: DEFAULT_PARTICLE_CSV.to_string(),
+            stats_log: None,
+            recording: false,
+            recorded_frames: 0,
+            frames_dir: DEFAULT_FRAMES_DIR.to_string(),
+        };
+        state.reset();
+        state
+    }
+
+
+    fn reset(&mut self) {
+        self.reset_keeping(Vec::new());
+    }
+
+    // Re-rolls the disk but keeps every hand-placed mass where it is now
+    fn soft_reset(&mut self) {
+        let kept = self.particles.iter().filter(|particle| particle.user_placed).cloned().collect();
+        self.reset_keeping(kept);
+    }
+
+    // Starts over with fresh central stars and disk around the `kept` particles,
+    // which the new disk is placed clear of
+    fn reset_keeping(&mut self, kept: Vec<Particle>) {
+        if !self.freeze_seed {
+            self.seed = rand::random();
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        self.particles.clear();
+        self.selected = None;
+        self.steps = 0;
+        self.rendered_frames = 0;
+        self.sim_time = 0.0;
+        self.bound_groups.clear();
+        self.star_mass_history.clear();
+        self.collapsed = false;
+        self.shakes = 0;
+        self.merges = 0;
+
+        let count = self.sliders[20].value as usize;
+        for mut star in central_stars(self.star_home, self.sliders[6].value, count, BINARY_STAR_SEPARATION) {
+            star.radius = self.radius_model.radius(star.mass);
+            star.central = true;
+            self.particles.push(star);
+        }
+
+        self.star_display_radius = self.particles[0].radius;
+        self.merge_effects.clear();
+        let kept_count = kept.len();
+        self.particles.extend(kept);
+
+        for _ in 0..self.particle_count {
+            let placed = sample_clear_of(&self.particles, SPAWN_ATTEMPTS, || self.spawn_particle(&mut rng));
+            if let Some(particle) = placed {
+                self.particles.push(particle);
+            }
+        }
+        self.star_count = count.max(1);
+        self.initial_orbiting = self.particles.len() - self.star_count;
+        let placed = self.initial_orbiting - kept_count;
+        self.conserved_at_reset = Conserved::measure(&self.particles);
+        self.leader = None;
+        self.leader_id = None;
+        self.leader_path.clear();
+        if placed < self.particle_count {
+            eprintln!(
+                "Only placed {} of {} particles without overlap; the spawn ring is too crowded",
+                placed, self.particle_count
+            );
+        }
+    }
+
+    // What new particles orbit: the star, or with several central stars their
+    // combined mass at its center, falling back to where reset puts the star
+    fn spawn_center(&self) -> (Point2<f32>, f32) {
+        let Some(star) = self.star_index() else {
+            return (self.star_home, 0.0);
+        };
+        let threshold = self.particles[star].mass * CENTRAL_STAR_FRACTION;
+        let central: Vec<Particle> = self.particles.iter().filter(|p| p.mass >= threshold).cloned().collect();
+        let mass = central.iter().map(|p| p.mass).sum();
+        let center = center_of_mass(&central).map_or(self.star_home, |c| Point2 { x: c.x, y: c.y });
+        (center, mass)
+    }
+
+    // A new particle drawn from the current scenario's distribution
+    fn spawn_particle(&self, rng: &mut impl Rng) -> Particle {
+        match self.scenario {
+            Scenario::AccretionDisk => self.spawn_orbiting_particle(rng),
+            Scenario::UniformCloud => self.spawn_cloud_particle(rng),
+        }
+    }
+
+    // Uniform over the spawn ring's area (its volume in 3D), moving in a
+    // random direction at about the local circular speed
+    fn spawn_cloud_particle(&self, rng: &mut impl Rng) -> Particle {
+        let (center, central_mass) = self.spawn_center();
+        let (x, y, z, distance) = if self.is_3d {
+            let distance = rng.gen_range(SPAWN_MIN_RADIUS.powi(3)..SPAWN_MAX_RADIUS.powi(3)).cbrt();
+            let cos_theta: f32 = rng.gen_range(-1.0..1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let phi = rng.gen_range(0.0..2.0 * PI);
+            (
+                center.x + distance * sin_theta * phi.cos(),
+                center.y + distance * sin_theta * phi.sin(),
+                distance * cos_theta,
+                distance,
+            )
+        } else {
+            let distance = rng.gen_range(SPAWN_MIN_RADIUS.powi(2)..SPAWN_MAX_RADIUS.powi(2)).sqrt();
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            (center.x + distance * angle.cos(), center.y + distance * angle.sin(), 0.0, distance)
+        };
+
+        let mut particle = self.new_particle(
+            x, y, z,
+            rng.gen_range(self.initial_mass_range.0..self.initial_mass_range.1)
+        );
+        particle.birth_color = birth_ring_color(distance);
+
+        // Split the circular speed evenly over the components so the cloud
+        // is about as bound as the disk, just without the rotation
+        let dimensions: f32 = if self.is_3d { 3.0 } else { 2.0 };
+        let sigma = (G * central_mass / distance).sqrt() * self.initial_velocity_multiplier / dimensions.sqrt();
+        particle.velocity = Vector3 {
+            x: sigma * gaussian(rng),
+            y: sigma * gaussian(rng),
+            z: if self.is_3d { sigma * gaussian(rng) } else { 0.0 },
+        };
+        particle
+    }
+
+    fn spawn_orbiting_particle(&self, rng: &mut impl Rng) -> Particle {
+        // Orbit wherever the star currently is, falling back to where reset puts it
+        let (center, central_mass) = self.spawn_center();
+        let (x, y, z, angle, phi, theta, distance) = if self.is_3d {
+            let distance = rng.gen_range(SPAWN_MIN_RADIUS..SPAWN_MAX_RADIUS);
+            let phi = rng.gen_range(0.0..2.0 * PI);
+            let theta = rng.gen_range(0.0..PI);
+            
+            (
+                center.x + distance * phi.sin() * theta.cos(),
+                center.y + distance * phi.sin() * theta.sin(),
+                distance * phi.cos(),
+                0.0,
+                phi,
+                theta,
+                distance
+            )
+        } else {
+            let distance = rng.gen_range(SPAWN_MIN_RADIUS..SPAWN_MAX_RADIUS);
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            (
+                center.x + distance * angle.cos(),
+                center.y + distance * angle.sin(),
+                0.0,
+                angle,
+                0.0,
+                0.0,
+                distance
+            )
+        };
+        
+        let mut particle = self.new_particle(
+            x, y, z,
+            rng.gen_range(self.initial_mass_range.0..self.initial_mass_range.1)
+        );
+        particle.birth_color = birth_ring_color(distance);
+
+        let orbital_speed = (G * central_mass / distance).sqrt() * self.initial_velocity_multiplier;
+        
+        particle.velocity = if self.is_3d {
+            Vector3 {
+                x: orbital_speed * (-phi.sin() * theta.sin()),
+                y: orbital_speed * (phi.sin() * theta.cos()),
+                z: orbital_speed * phi.cos(),
+            }
+        } else {
+            Vector3 {
+                x: -orbital_speed * angle.sin(),
+                y: orbital_speed * angle.cos(),
+                z: 0.0,
+            }
+        };
+        // Some fraction of the disk goes round the other way
+        if rng.gen_bool(self.sliders[17].value.clamp(0.0, 1.0) as f64) {
+            particle.velocity.x = -particle.velocity.x;
+            particle.velocity.y = -particle.velocity.y;
+            particle.velocity.z = -particle.velocity.z;
+        }
+
+        particle
+    }
+
+    // Match the orbiting population to particle_count without a reset,
+    // spawning extras from the usual distribution and dropping the lightest
+    fn apply_particle_count(&mut self) {
+        // Everything may have been deleted; orbiting particles need a star
+        if self.particles.is_empty() {
+            let mut star = self.new_particle(self.star_home.x, self.star_home.y, 0.0, self.sliders[6].value);
+            star.central = true;
+            self.star_display_radius = star.radius;
+            self.particles.push(star);
+            self.star_count = 1;
+        }
+        let current = self.particles.len().saturating_sub(self.star_count);
+        if self.particle_count > current {
+            // Derived from the run's seed and state so a seeded session can be replayed
+            let mut rng = StdRng::seed_from_u64(self.seed ^ self.steps.rotate_left(32) ^ self.particles.len() as u64);
+            for _ in current..self.particle_count {
+                let particle = self.spawn_particle(&mut rng);
+                self.particles.push(particle);
+            }
+        } else if self.particle_count < current {
+            let excess = current - self.particle_count;
+            let star = self.star_index();
+            let mut by_mass: Vec<usize> = (0..self.particles.len()).filter(|&i| Some(i) != star).collect();
+            by_mass.sort_by(|&a, &b| self.particles[a].mass.total_cmp(&self.particles[b].mass));
+            let mut doomed = by_mass[..excess.min(by_mass.len())].to_vec();
+            doomed.sort_unstable();
+            for index in doomed.into_iter().rev() {
+                self.remove_particle(index);
+            }
+        }
+        self.initial_orbiting = self.particles.len().saturating_sub(self.star_count);
+    }
+
+    fn force_settings(&self) -> ForceSettings {
+        ForceSettings {
+            is_3d: self.is_3d,
+            gravity: self.gravity_enabled,
+            exponent: self.sliders[11].value,
+            drag: self.sliders[13].value,
+            max_acceleration: self.sliders[15].value,
+            cutoff: self.sliders[18].value,
+            softening: self.sliders[4].value,
+            size_softening: self.size_softening,
+        }
+    }
+
+    fn units(&self) -> Units {
+        if self.use_real_units {
+            self.real_units
+        } else {
+            Units::SIMULATION
+        }
+    }
+
+    // Time step for one update: Time Step scaled by Time Speed, optionally
+    // locked to a power of two
+    fn frame_dt(&self) -> f32 {
+        let dt = self.sliders[5].value * self.sliders[0].value;
+        if self.lock_dt_power_of_two {
+            snap_to_power_of_two(dt)
+        } else {
+            dt
+        }
+    }
+
+    // Forces are recomputed every this many steps and reused in between
+    fn force_interval(&self) -> u64 {
+        self.sliders[7].value.round().max(1.0) as u64
+    }
+
+    // Advances the simulation by a single integration step
+    fn step(&mut self, dt: f32) {
+        // Stale accelerations mustn't outlive switching gravity off
+        let recompute_forces = self.steps.is_multiple_of(self.force_interval()) || !self.gravity_enabled;
+        let settings = self.force_settings();
+        let force_start = Instant::now();
+        let particles_snapshot = self.particles.clone();
+        // The quadtree only covers the x/y plane, so 3D always sums directly
+        let tree = (recompute_forces && self.force_mode == ForceMode::BarnesHut && !self.is_3d && !self.double_precision)
+            .then(|| QuadNode::build(&particles_snapshot));
+        if self.double_precision {
+            step_f64(&mut self.particles, dt as f64, &settings);
+        } else {
+            for (i, particle) in self.particles.iter_mut().enumerate() {
+                particle.update(dt, Some(i), &particles_snapshot, tree.as_ref(), &settings, recompute_forces);
+            }
+        }
+        if let Some((cursor, sign)) = self.gun {
+            let cursor = self.screen_to_world(cursor);
+            let target = Point3 { x: cursor.x, y: cursor.y, z: 0.0 };
+            let strength = sign * self.sliders[24].value;
+            for particle in self.particles.iter_mut().filter(|particle| !particle.fixed) {
+                let pull = gun_acceleration(particle.position, target, strength);
+                particle.velocity.x += pull.x * dt;
+                particle.velocity.y += pull.y * dt;
+                particle.velocity.z += pull.z * dt;
+            }
+        }
+        for particle in &mut self.particles {
+            particle.age += 1;
+            if let Some(trail) = particle.trail.as_mut() {
+                if trail.len() == TRAIL_LENGTH {
+                    trail.pop_front();
+                }
+                trail.push_back(particle.position);
+            }
+        }
+        self.force_timing.record(force_start.elapsed());
+        // A Max Speed of zero leaves velocities unclamped
+        let max_speed = self.sliders[10].value;
+        if max_speed > 0.0 {
+            for particle in &mut self.particles {
+                clamp_magnitude(&mut particle.velocity, max_speed);
+            }
+        }
+        if self.boundary_mode != BoundaryMode::Open {
+            for particle in self.particles.iter_mut().filter(|particle| !particle.fixed) {
+                apply_boundary(particle, self.boundary_mode, self.world_bounds);
+            }
+        }
+        let collision_start = Instant::now();
+        self.handle_collisions();
+        self.collision_timing.record(collision_start.elapsed());
+        self.steps += 1;
+        self.sim_time += dt;
+        self.run_script_events();
+        if let Some(star) = self.star_index() {
+            self.star_mass_history.record(self.steps, self.particles[star].mass);
+        }
+
+        self.log_stats();
+        self.update_phase();
+        self.track_selected_period();
+        if self.track_leader {
+            self.record_leader();
+        }
+        let orbiting = self.particles.len().saturating_sub(self.star_count);
+        if !self.collapsed && has_collapsed(orbiting, self.initial_orbiting) {
+            self.collapsed = true;
+            println!("Only {} bodies left orbiting; try a higher Velocity", orbiting);
+        }
+
+        if self.show_clusters && self.steps.is_multiple_of(BINARY_SCAN_INTERVAL) {
+            self.scan_clusters();
+        }
+        if self.show_binaries && self.steps.is_multiple_of(BINARY_SCAN_INTERVAL) {
+            let star = self.star_index().unwrap_or(usize::MAX);
+            self.bound_groups = find_bound_groups(&self.particles, star);
+        }
+    }
+
+    // Runs the physics for one rendered frame: a single step normally, or
+    // `frame_skip` of them when fast-forwarding
+    fn advance_frame(&mut self) {
+        for particle in &mut self.particles {
+            particle.prev_position = particle.position;
+        }
+        for _ in 0..self.frame_skip {
+            if self.tick() {
+                self.toggle_pause();
+                println!("Reached step {}, paused", self.steps);
+                break;
+            }
+        }
+        self.rendered_frames += 1;
+    }
+
+    // One step plus the bookkeeping that follows it, shared by the window and
+    // headless runs. True when the step just taken is the Stop At Step target.
+    fn tick(&mut self) -> bool {
+        let selected_before = self.selected;
+        self.step(self.frame_dt());
+        // The selection follows whatever swallowed it, and the focus ring
+        // replays its intro to flag the merge
+        if let Some(survivor) = selected_before.and_then(|index| surviving_index(&self.collision_events, index)) {
+            self.selected = Some(survivor);
+            self.focus = None;
+        }
+        for event in self.collision_events.drain(..) {
+            if event.kind == CollisionKind::Accretion {
+                self.merge_effects.push(MergeEffect { position: event.position, age: 0 });
+            }
+        }
+        self.expire_particles();
+        at_step_target(self.steps, self.sliders[19].value as u64)
+    }
+
+    // Advances everything that's only for show by `real_dt` seconds. Never
+    // touches the physics, so it keeps going while the simulation is paused.
+    fn animate(&mut self, real_dt: f32) {
+        self.anim_time += real_dt;
+        if let Some(flight) = self.camera_flight.as_mut() {
+            flight.elapsed += real_dt;
+            (self.zoom, self.pan) = if flight.done() { (flight.to_zoom, flight.to_pan) } else { flight.current() };
+            if flight.done() {
+                self.camera_flight = None;
+            }
+        }
+        for effect in &mut self.merge_effects {
+            effect.age += 1;
+        }
+        self.merge_effects.retain(|effect| effect.age < MERGE_EFFECT_LIFETIME);
+        if let Some(star) = self.star_index() {
+            self.star_display_radius += (self.particles[star].radius - self.star_display_radius) * 0.1;
+        }
+    }
+
+    // Relabels friends-of-friends clusters and refreshes their count and heaviest mass
+    fn scan_clusters(&mut self) {
+        self.cluster_labels = friends_of_friends(&self.particles, CLUSTER_LINKING_LENGTH, self.star_index());
+        let mut masses: Vec<f32> = Vec::new();
+        for (label, particle) in self.cluster_labels.iter().zip(&self.particles) {
+            if let Some(label) = *label {
+                if masses.len() <= label {
+                    masses.resize(label + 1, 0.0);
+                }
+                masses[label] += particle.mass;
+            }
+        }
+        self.cluster_summary = (masses.len(), masses.iter().copied().fold(0.0, f32::max));
+    }
+
+    // Re-labels the phase once every PHASE_WINDOW steps
+    fn update_phase(&mut self) {
+        let (since_step, since_merges) = self.phase_mark;
+        // A reset or snapshot restore moved the counters back
+        if self.steps < since_step || self.merges < since_merges {
+            self.phase_mark = (self.steps, self.merges);
+            self.phase = None;
+            return;
+        }
+        let elapsed = self.steps - since_step;
+        if elapsed < PHASE_WINDOW {
+            return;
+        }
+        let merges_per_100 = 100.0 * (self.merges - since_merges) as f32 / elapsed as f32;
+        let (energy_drift, _, _) = self.conserved_at_reset.drift(&Conserved::measure(&self.particles));
+        // The star would dominate both sums, so only the orbiting mass counts
+        let star = self.star_index();
+        let orbiting = self.particles.iter().enumerate().filter(|&(i, _)| Some(i) != star).map(|(_, p)| p.mass);
+        let total: f32 = orbiting.clone().sum();
+        let heaviest = orbiting.fold(0.0, f32::max);
+        let concentration = if total > 0.0 { heaviest / total } else { 0.0 };
+        self.phase = Some(classify_phase(merges_per_100, energy_drift, concentration));
+        self.phase_mark = (self.steps, self.merges);
+    }
+
+    // Applies every scripted event scheduled for the step just finished
+    fn run_script_events(&mut self) {
+        let due: Vec<ScriptAction> =
+            self.script.iter().filter(|event| event.step == self.steps).map(|event| event.action).collect();
+        for action in due {
+            match action {
+                ScriptAction::AddMass { x, y, vx, vy, mass } => {
+                    let mut particle = self.new_particle(x, y, 0.0, mass);
+                    particle.velocity = Vector3 { x: vx, y: vy, z: 0.0 };
+                    self.particles.push(particle);
+                }
+                ScriptAction::Explode { x, y, strength, radius } => {
+                    for particle in self.particles.iter_mut().filter(|particle| !particle.fixed) {
+                        let (dx, dy) = (particle.position.x - x, particle.position.y - y);
+                        let distance = (dx * dx + dy * dy).sqrt();
+                        if distance < radius && distance > f32::EPSILON {
+                            let kick = strength * (1.0 - distance / radius) / distance;
+                            particle.velocity.x += dx * kick;
+                            particle.velocity.y += dy * kick;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Removes (or, when recycling, respawns at the disk's edge) every particle
+    // that has reached the Lifetime slider's age. The central stars (or the
+    // heaviest body, for loaded files), fixed anchors, and tracers live forever.
+    fn expire_particles(&mut self) {
+        let lifetime = self.sliders[21].value as u32;
+        if lifetime == 0 {
+            return;
+        }
+        let star = self.star_index();
+        // Seeded like apply_particle_count so recycling replays with the run
+        let mut rng = StdRng::seed_from_u64(self.seed ^ self.steps.rotate_left(32) ^ self.particles.len() as u64);
+        for i in (0..self.particles.len()).rev() {
+            let particle = &self.particles[i];
+            if Some(i) == star || particle.central || particle.fixed || particle.tracer || !has_expired(particle.age, lifetime) {
+                continue;
+            }
+            if self.recycle_expired {
+                self.particles[i] = self.spawn_at_edge(&mut rng);
+                if self.selected == Some(i) {
+                    self.selected = None;
+                }
+            } else {
+                self.remove_particle(i);
+            }
+        }
+    }
+
+    // A fresh particle on a circular orbit at the outer edge of the spawn ring
+    fn spawn_at_edge(&self, rng: &mut impl Rng) -> Particle {
+        let (center, central_mass) = self.spawn_center();
+        let angle = rng.gen_range(0.0..2.0 * PI);
+        let mut particle = self.new_particle(
+            center.x + SPAWN_MAX_RADIUS * angle.cos(),
+            center.y + SPAWN_MAX_RADIUS * angle.sin(),
+            0.0,
+            rng.gen_range(self.initial_mass_range.0..self.initial_mass_range.1),
+        );
+        particle.birth_color = birth_ring_color(SPAWN_MAX_RADIUS);
+        let speed = (G * central_mass / SPAWN_MAX_RADIUS).sqrt() * self.initial_velocity_multiplier;
+        particle.velocity = Vector3 { x: -speed * angle.sin(), y: speed * angle.cos(), z: 0.0 };
+        particle
+    }
+
+    // Merges every overlapping pair, the heavier body absorbing the lighter,
+    // and leaves a ripple wherever something falls into the star
+    fn handle_collisions(&mut self) {
+        self.collision_events.clear();
+        let mut pairs = if self.particles.len() < GRID_COLLISION_THRESHOLD {
+            colliding_pairs_brute(&self.particles, self.sliders[22].value, self.sliders[23].value)
+        } else {
+            colliding_pairs_grid(&self.particles, self.sliders[22].value, self.sliders[23].value)
+        };
+        pairs.retain(|&(a, b)| !self.particles[a].tracer && !self.particles[b].tracer);
+        if pairs.is_empty() {
+            return;
+        }
+
+        let star = self.star_index();
+        let mut absorbed = vec![false; self.particles.len()];
+        for (a, b) in pairs {
+            if absorbed[a] || absorbed[b] {
+                continue;
+            }
+            // Fast impacts bounce instead of sticking
+            let merge_speed = self.sliders[14].value;
+            if merge_speed > 0.0 && relative_speed(&self.particles[a], &self.particles[b]) > merge_speed {
+                let (low, high) = self.particles.split_at_mut(b.max(a));
+                elastic_bounce(&mut low[a.min(b)], &mut high[0]);
+                let position = self.particles[b].position;
+                self.collision_events.push(CollisionEvent { a, b, kind: CollisionKind::Bounce, position });
+                continue;
+            }
+            let (keep, gone) = if self.particles[a].mass >= self.particles[b].mass { (a, b) } else { (b, a) };
+            let other = self.particles[gone].clone();
+            let kind = if Some(keep) == star { CollisionKind::Accretion } else { CollisionKind::Merge };
+            self.collision_events.push(CollisionEvent { a: keep, b: gone, kind, position: other.position });
+            self.merges += 1;
+            self.particles[keep].absorb(&other, self.radius_model);
+            absorbed[gone] = true;
+        }
+        for i in (0..self.particles.len()).rev() {
+            if absorbed[i] {
+                self.remove_particle(i);
+            }
+        }
+    }
+
+    // Replaces the system with particles read from a CSV file. The most massive
+    // one becomes the central star. On failure the current system is kept.
+    fn load_particles_csv(&mut self, path: &str) -> Result<(), String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let mut particles = parse_particle_csv(&contents, &self.real_units).map_err(|err| format!("{}: {}", path, err))?;
+        if let Some(star) = (0..particles.len()).max_by(|&a, &b| particles[a].mass.total_cmp(&particles[b].mass)) {
+            particles.swap(0, star);
+        }
+        for particle in &mut particles {
+            particle.radius = self.radius_model.radius(particle.mass);
+        }
+        self.replace_particles(particles, 1);
+        Ok(())
+    }
+
+    // Starts a fresh run from a ready-made set of particles, the first `stars`
+    // of them being the central stars
+    fn replace_particles(&mut self, particles: Vec<Particle>, stars: usize) {
+        self.star_count = stars;
+        self.initial_orbiting = particles.len().saturating_sub(stars);
+        self.collapsed = false;
+        self.particles = particles;
+        self.selected = None;
+        self.bound_groups.clear();
+        self.merge_effects.clear();
+        self.steps = 0;
+        self.rendered_frames = 0;
+        self.sim_time = 0.0;
+        self.star_mass_history.clear();
+        self.star_display_radius = self.particles.first().map_or(0.0, |star| star.radius);
+        self.conserved_at_reset = Conserved::measure(&self.particles);
+        self.leader = None;
+        self.leader_id = None;
+        self.leader_path.clear();
+    }
+
+    // Two stars sharing the Central Mass on a circular mutual orbit, with a
+    // circumbinary disk orbiting the pair's total mass further out
+    fn load_binary_preset(&mut self) {
+        if !self.freeze_seed {
+            self.seed = rand::random();
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let total = self.sliders[6].value;
+        let [mut a, mut b] = binary_star_pair(self.star_home, total * 0.5, total * 0.5, BINARY_STAR_SEPARATION);
+        a.radius = self.radius_model.radius(a.mass);
+        b.radius = self.radius_model.radius(b.mass);
+        (a.central, b.central) = (true, true);
+        let mut particles = vec![a, b];
+        for _ in 0..self.particle_count {
+            let distance = rng.gen_range(SPAWN_MIN_RADIUS..SPAWN_MAX_RADIUS);
+            let angle = rng.gen_range(0.0..2.0 * PI);
+            let mass = rng.gen_range(self.initial_mass_range.0..self.initial_mass_range.1);
+            let mut particle = self.new_particle(
+                self.star_home.x + distance * angle.cos(),
+                self.star_home.y + distance * angle.sin(),
+                0.0,
+                mass,
+            );
+            let mut speed = orbit_velocity(total, mass, distance, 0.0) * self.initial_velocity_multiplier;
+            if rng.gen_bool(self.sliders[17].value.clamp(0.0, 1.0) as f64) {
+                speed = -speed;
+            }
+            particle.velocity = Vector3 { x: -speed * angle.sin(), y: speed * angle.cos(), z: 0.0 };
+            particle.birth_color = birth_ring_color(distance);
+            particles.push(particle);
+        }
+        self.replace_particles(particles, 2);
+    }
+
+    fn potential_at(&self, x: f32, y: f32) -> f32 {
+        self.particles
+            .iter()
+            .map(|particle| {
+                let dx = particle.position.x - x;
+                let dy = particle.position.y - y;
+                -G * particle.mass / (dx * dx + dy * dy + 1.0).sqrt()
+            })
+            .sum()
+    }
+
+    // Iso-potential curves over the visible region. Levels are spaced like the
+    // potential of the total mass at evenly spaced radii, so for a lone star
+    // they come out as evenly spaced circles.
+    fn potential_contours(&self) -> Vec<[Point2<f32>; 2]> {
+        let cols = (WINDOW_WIDTH / CONTOUR_CELL_SIZE) as usize + 1;
+        let rows = (WINDOW_HEIGHT / CONTOUR_CELL_SIZE) as usize + 1;
+        let cell = CONTOUR_CELL_SIZE / self.zoom;
+        let (origin_x, origin_y) = (-self.pan.x, -self.pan.y);
+
+        let mut field = Vec::with_capacity(cols * rows);
+        for r in 0..rows {
+            for c in 0..cols {
+                field.push(self.potential_at(origin_x + c as f32 * cell, origin_y + r as f32 * cell));
+            }
+        }
+
+        let total_mass: f32 = self.particles.iter().map(|particle| particle.mass).sum();
+        let spacing = WINDOW_WIDTH.max(WINDOW_HEIGHT) / self.zoom / (2.0 * CONTOUR_LEVELS as f32);
+        let mut segments = Vec::new();
+        for k in 1..=CONTOUR_LEVELS {
+            let level = -G * total_mass / (spacing * k as f32);
+            for [a, b] in marching_squares(&field, cols, rows, level) {
+                let to_world = |p: Point2<f32>| Point2 { x: origin_x + p.x * cell, y: origin_y + p.y * cell };
+                segments.push([to_world(a), to_world(b)]);
+            }
+        }
+        segments
+    }
+
+    // Placement, size and color of every particle's instance of the shared
+    // unit-circle mesh, plus the motion-blur streaks to draw under them.
+    // `attractor` is what isolate mode keeps visible besides the selection.
+    fn particle_instances(&self, attractor: Option<(usize, f32)>) -> (Vec<DrawParam>, Vec<Streak>) {
+        // How far into the current smooth-motion tick this frame falls
+        let alpha = if self.smooth_motion && !self.paused { self.physics_clock / SMOOTH_TICK } else { 1.0 };
+        let star = self.star_index();
+        // Motion-blur streaks, gathered while sizing and coloring the dots
+        let mut streaks: Vec<Streak> = Vec::new();
+        let instances: Vec<DrawParam> = self
+            .particles
+            .iter()
+            .enumerate()
+            .map(|(i, particle)| {
+                let pos = self.world_to_screen(&particle.interpolated_position(alpha));
+                let mut color = match self.selected {
+                    Some(selected) if self.isolate && i != selected && Some(i) != attractor.map(|(a, _)| a) => {
+                        Color::new(1.0, 1.0, 1.0, 0.1)
+                    }
+                    // Labels are from the last scan, so merges since can shift them by a few
+                    _ if self.show_clusters => match self.cluster_labels.get(i).copied().flatten() {
+                        Some(label) => hue_color(label as f32 * 0.618),
+                        None => Color::new(0.5, 0.5, 0.5, 0.4),
+                    },
+                    _ if self.show_birth_colors => particle.birth_color,
+                    _ => Color::WHITE,
+                };
+                // The initial population is as old as the run and never fades;
+                // anything younger was added later
+                if (particle.age as u64) < self.steps {
+                    color.a *= fade_in_alpha(particle.age);
+                }
+                let radius = if particle.tracer {
+                    color.a *= 0.5;
+                    TRACER_DRAW_RADIUS
+                } else if Some(i) == star {
+                    self.star_display_radius * self.zoom
+                } else {
+                    particle.radius * self.zoom
+                };
+                if self.motion_blur {
+                    let (from, to) = particle.streak(alpha);
+                    let (from, to) = (self.world_to_screen(&from), self.world_to_screen(&to));
+                    // Only moves longer than the dot itself are worth a streak
+                    if (to.x - from.x).hypot(to.y - from.y) > radius {
+                        streaks.push((from, to, (2.0 * radius).max(1.0), Color { a: color.a * 0.4, ..color }));
+                    }
+                }
+                DrawParam::default().dest(pos).scale([radius, radius]).color(color)
+            })
+            .collect();
+        (instances, streaks)
+    }
+
+    // Keplerian ellipses for every bound body the star dominates; the
+    // selection falls back to a forward-integrated predicted path when it
+    // isn't dominated
+    fn draw_orbits(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let Some(star_index) = self.star_index() else {
+            return Ok(());
+        };
+        let star = &self.particles[star_index];
+        let exponent = self.sliders[11].value;
+        let mut builder = graphics::MeshBuilder::new();
+        let mut any = false;
+        for (i, body) in self.particles.iter().enumerate() {
+            if i == star_index || star_dominance(&self.particles, i, star_index, exponent) < STAR_DOMINANCE_THRESHOLD {
+                continue;
+            }
+            let position = Point2 { x: body.position.x - star.position.x, y: body.position.y - star.position.y };
+            let velocity = Point2 { x: body.velocity.x - star.velocity.x, y: body.velocity.y - star.velocity.y };
+            let Some(elements) = OrbitalElements::from_state(position, velocity, G * (star.mass + body.mass)) else {
+                continue;
+            };
+            let points: Vec<Point2<f32>> = (0..ORBIT_ELLIPSE_SEGMENTS)
+                .map(|k| {
+                    let offset = elements.point_at(k as f32 / ORBIT_ELLIPSE_SEGMENTS as f32 * 2.0 * PI);
+                    self.world_to_screen(&Point3 {
+                        x: star.position.x + offset.x,
+                        y: star.position.y + offset.y,
+                        z: star.position.z,
+                    })
+                })
+                .collect();
+            let alpha = if Some(i) == self.selected { 0.8 } else { 0.2 };
+            builder.polygon(graphics::DrawMode::stroke(1.0), &points, Color::new(0.4, 0.8, 1.0, alpha))?;
+            any = true;
+        }
+
+        if let Some(selected) = self.selected.filter(|&i| i != star_index) {
+            if star_dominance(&self.particles, selected, star_index, exponent) < STAR_DOMINANCE_THRESHOLD {
+                let path = predict_path(&self.particles, selected, &self.force_settings(), self.frame_dt(), PREDICTION_STEPS);
+                let points: Vec<Point2<f32>> = path.iter().map(|position| self.world_to_screen(position)).collect();
+                builder.line(&points, 1.0, Color::new(1.0, 0.6, 0.3, 0.8))?;
+                any = true;
+            }
+        }
+
+        if any {
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::default());
+        }
+        Ok(())
+    }
+
+    // Line plot of a history in a panel, scaled to fit its value range
+    fn draw_history_plot(
+        &self,
+        ctx: &mut Context,
+        canvas: &mut graphics::Canvas,
+        history: &History,
+        panel: graphics::Rect,
+        title: &str,
+    ) -> GameResult {
+        let background = Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), panel, Color::new(0.1, 0.1, 0.1, 0.8))?;
+        canvas.draw(&background, DrawParam::default());
+
+        let samples = &history.samples;
+        let min = samples.iter().map(|&(_, v)| v).fold(f32::MAX, f32::min);
+        let max = samples.iter().map(|&(_, v)| v).fold(f32::MIN, f32::max);
+        let label = if samples.is_empty() { title.to_string() } else { format!("{} ({:.0} - {:.0})", title, min, max) };
+        canvas.draw(&Text::new(label), DrawParam::default().dest([panel.x + 5.0, panel.y + 3.0]).color(Color::WHITE));
+
+        if samples.len() >= 2 {
+            let (first_step, last_step) = (samples[0].0 as f32, samples[samples.len() - 1].0 as f32);
+            let step_span = (last_step - first_step).max(1.0);
+            let value_span = (max - min).max(f32::EPSILON);
+            let plot_top = panel.y + 22.0;
+            let plot_height = panel.h - 27.0;
+            let points: Vec<Point2<f32>> = samples
+                .iter()
+                .map(|&(step, value)| Point2 {
+                    x: panel.x + 5.0 + (panel.w - 10.0) * (step as f32 - first_step) / step_span,
+                    y: plot_top + plot_height * (1.0 - (value - min) / value_span),
+                })
+                .collect();
+            let line = Mesh::new_line(ctx, &points, 1.5, Color::from_rgb(255, 200, 80))?;
+            canvas.draw(&line, DrawParam::default());
+        }
+        Ok(())
+    }
+
+    // Tidal stretch on the selected particle from the star
+    fn selected_tidal_acceleration(&self) -> Option<f32> {
+        let selected = self.selected?;
+        let star = self.star_index().filter(|&star| star != selected)?;
+        let (body, star) = (&self.particles[selected], &self.particles[star]);
+        let dx = body.position.x - star.position.x;
+        let dy = body.position.y - star.position.y;
+        let dz = body.position.z - star.position.z;
+        tidal_acceleration(star.mass, (dx * dx + dy * dy + dz * dz).sqrt(), body.radius)
+    }
+
+    // Keeps the period tracker following the selected particle's angle about the star
+    fn track_selected_period(&mut self) {
+        let (Some(selected), Some(star)) = (self.selected, self.star_index()) else {
+            self.period_tracker = None;
+            return;
+        };
+        if selected == star {
+            self.period_tracker = None;
+            return;
+        }
+        if self.period_tracker.as_ref().is_none_or(|tracker| tracker.index != selected) {
+            self.period_tracker = Some(PeriodTracker::new(selected));
+        }
+        let (body, star) = (&self.particles[selected].position, &self.particles[star].position);
+        let angle = (body.y - star.y).atan2(body.x - star.x);
+        if let Some(tracker) = self.period_tracker.as_mut() {
+            tracker.observe(angle, self.sim_time);
+        }
+    }
+
+    // Follows whichever particle is currently heaviest, extending its path.
+    // When a different body (by id) takes the lead a new stretch of the path
+    // starts instead of drawing a line across.
+    fn record_leader(&mut self) {
+        let Some(leader) = self.star_index() else {
+            return;
+        };
+        let particle = &self.particles[leader];
+        if self.leader_id.is_some_and(|id| id != particle.id) {
+            self.leader_path.push_back(None);
+        }
+        self.leader = Some(leader);
+        self.leader_id = Some(particle.id);
+        self.leader_path.push_back(Some(particle.position));
+        while self.leader_path.len() > LEADER_PATH_LENGTH {
+            self.leader_path.pop_front();
+        }
+    }
+
+    fn toggle_stats_log(&mut self) {
+        if let Some(mut log) = self.stats_log.take() {
+            if let Err(err) = log.flush() {
+                eprintln!("Failed to finish {}: {}", DEFAULT_STATS_CSV, err);
+            }
+            println!("Stopped logging statistics to {}", DEFAULT_STATS_CSV);
+            return;
+        }
+        let opened = File::create(DEFAULT_STATS_CSV).and_then(|file| {
+            let mut log = BufWriter::new(file);
+            writeln!(log, "{}", STATS_HEADER)?;
+            Ok(log)
+        });
+        match opened {
+            Ok(log) => {
+                self.stats_log = Some(log);
+                println!("Logging statistics to {}", DEFAULT_STATS_CSV);
+            }
+            Err(err) => eprintln!("Can't create {}: {}", DEFAULT_STATS_CSV, err),
+        }
+    }
+
+    fn log_stats(&mut self) {
+        let Some(log) = self.stats_log.as_mut() else {
+            return;
+        };
+        if let Err(err) = writeln!(log, "{}", stats_row(self.steps, self.sim_time, &self.particles)) {
+            eprintln!("Stopped logging statistics, failed to write {}: {}", DEFAULT_STATS_CSV, err);
+            self.stats_log = None;
+        }
+    }
+
+    fn toggle_recording(&mut self, ctx: &Context) {
+        if self.recording {
+            self.recording = false;
+            println!("Stopped recording after {} frames", self.recorded_frames);
+            return;
+        }
+        if let Err(err) = ctx.fs.create_dir(&self.frames_dir) {
+            eprintln!("Can't create frame directory {}: {}", self.frames_dir, err);
+            return;
+        }
+        self.recording = true;
+        self.recorded_frames = 0;
+        println!(
+            "Recording frames to {}{} (one PNG per frame, this fills the disk quickly)",
+            ctx.fs.user_data_dir().display(),
+            self.frames_dir
+        );
+    }
+
+    // Writes the frame just rendered as the next numbered PNG
+    fn record_frame(&mut self, ctx: &Context) {
+        self.save_frame(|path| ctx.gfx.frame().encode(ctx, graphics::ImageEncodingFormat::Png, path).map_err(|err| err.to_string()));
+    }
+
+    // Hands `write` the next numbered frame path, counting the frame if it
+    // was written and stopping the recording if not
+    fn save_frame(&mut self, write: impl FnOnce(&str) -> Result<(), String>) {
+        let path = format!("{}/frame_{:06}.png", self.frames_dir, self.recorded_frames);
+        match write(&path) {
+            Ok(()) => self.recorded_frames += 1,
+            Err(err) => {
+                eprintln!("Stopped recording, failed to write {}: {}", path, err);
+                self.recording = false;
+            }
+        }
+    }
+
+    // The central star is the most massive body, which is index 0 after a
+    // reset but not necessarily after masses are added or removed
+    fn star_index(&self) -> Option<usize> {
+        self.particles
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.mass.total_cmp(&b.mass))
+            .map(|(i, _)| i)
+    }
+
+    // Removes everything except the central star, leaving a blank slate for
+    // hand-built configurations
+    fn clear_to_star(&mut self) {
+        if let Some(index) = self.star_index() {
+            let star = self.particles.swap_remove(index);
+            self.particles.clear();
+            self.particles.push(star);
+        }
+        self.selected = None;
+        self.bound_groups.clear();
+        // A deliberate blank slate isn't a collapse
+        self.initial_orbiting = 0;
+        self.star_count = 1;
+        self.collapsed = false;
+    }
+
+    // One-off velocity change applied to the central star only
+    fn kick_star(&mut self, impulse: Vector3<f32>) {
+        if let Some(star) = self.star_index().map(|i| &mut self.particles[i]) {
+            star.velocity.x += impulse.x;
+            star.velocity.y += impulse.y;
+            star.velocity.z += impulse.z;
+        }
+    }
+
+    fn kick_from_sliders(&self) -> Vector3<f32> {
+        let speed = self.sliders[8].value;
+        let angle = self.sliders[9].value.to_radians();
+        Vector3 { x: speed * angle.cos(), y: speed * angle.sin(), z: 0.0 }
+    }
+
+    // Shifts the selected particle's position or velocity by one nudge step
+    // in the given direction
+    fn nudge_selected(&mut self, dx: f32, dy: f32, velocity: bool) {
+        let Some(particle) = self.selected.and_then(|i| self.particles.get_mut(i)) else {
+            return;
+        };
+        if velocity {
+            particle.velocity.x += dx * NUDGE_VELOCITY_STEP;
+            particle.velocity.y += dy * NUDGE_VELOCITY_STEP;
+        } else {
+            particle.position.x += dx * NUDGE_POSITION_STEP;
+            particle.position.y += dy * NUDGE_POSITION_STEP;
+        }
+    }
+
+    // Removes a particle while keeping the selection pointing at the same body
+    fn remove_particle(&mut self, index: usize) {
+        self.particles.remove(index);
+        self.selected = match self.selected {
+            Some(selected) if selected == index => None,
+            Some(selected) if selected > index => Some(selected - 1),
+            other => other,
+        };
+    }
+
+    // Inverse of the flat 2D view transform (the tilt and 3D projections aren't inverted)
+    fn screen_to_world(&self, point: Point2<f32>) -> Point2<f32> {
+        let point = rotate_about(point, WINDOW_CENTER, -self.view_angle);
+        if let Some(origin) = self.frame_origin() {
+            return Point2 {
+                x: (point.x - WINDOW_WIDTH / 2.0) / self.zoom + origin.x,
+                y: (point.y - WINDOW_HEIGHT / 2.0) / self.zoom + origin.y,
+            };
+        }
+        Point2 {
+            x: point.x / self.zoom - self.pan.x,
+            y: point.y / self.zoom - self.pan.y,
+        }
+    }
+
+    fn world_to_screen(&self, position: &Point3<f32>) -> Point2<f32> {
+        // In a particle's rest frame everything is drawn relative to it,
+        // with the particle itself pinned to the window center
+        if let Some(origin) = self.frame_origin() {
+            let relative = Point3 { x: position.x - origin.x, y: position.y - origin.y, z: position.z - origin.z };
+            if self.is_3d {
+                return relative.project_to_2d(self.zoom, self.rotation_x, self.rotation_y);
+            }
+            let screen = Point2 {
+                x: relative.x * self.zoom + WINDOW_WIDTH / 2.0,
+                y: relative.y * self.zoom + WINDOW_HEIGHT / 2.0,
+            };
+            let screen = rotate_about(screen, WINDOW_CENTER, self.view_angle);
+            return if self.tilted { tilt_projection(screen) } else { screen };
+        }
+        if self.is_3d {
+            position.project_to_2d(self.zoom, self.rotation_x, self.rotation_y)
+        } else {
+            let screen = Point2 {
+                x: (position.x + self.pan.x) * self.zoom,
+                y: (position.y + self.pan.y) * self.zoom,
+            };
+            let screen = rotate_about(screen, WINDOW_CENTER, self.view_angle);
+            if self.tilted {
+                tilt_projection(screen)
+            } else {
+                screen
+            }
+        }
+    }
+
+    // Position of the body whose rest frame the view is in, if any
+    fn frame_origin(&self) -> Option<Point3<f32>> {
+        self.selected.filter(|_| self.rest_frame).map(|index| self.particles[index].position)
+    }
+
+    // Topmost particle drawn under the given screen point, if any
+    fn particle_at(&self, point: Point2<f32>) -> Option<usize> {
+        self.particles.iter().enumerate().rev().find_map(|(i, particle)| {
+            let pos = self.world_to_screen(&particle.position);
+            let pick_radius = (particle.radius * self.zoom).max(5.0);
+            let (dx, dy) = (pos.x - point.x, pos.y - point.y);
+            (dx * dx + dy * dy <= pick_radius * pick_radius).then_some(i)
+        })
+    }
+
+    // `instant` (Shift held) skips camera animations the click would start
+    fn handle_mouse_click(&mut self, x: f32, y: f32, instant: bool) {
+        let mouse_pos = Point2 { x, y };
+        
+        // Handle UI elements first
+        let mut clicked_reset = false;
+        let mut should_pause = false;
+        let mut start_add_mass = false;
+        let mut toggle_radius = false;
+        let mut shake = false;
+        let mut toggle_scenario = false;
+        let mut soft_reset = false;
+        
+        // Only handle UI if not in mass-adding mode
+        if !self.adding_mass {
+        for button in &mut self.buttons {
+            if button.contains(mouse_pos, self.ui_scale) {
+                button.clicked = true;
+                match button.text.as_str() {
+                    "Run/Pause" => should_pause = true,
+                    "Reset" => clicked_reset = true,
+                    "Add Mass" => start_add_mass = true,
+                    "2D/3D" => self.is_3d = !self.is_3d,
+                    "Radius" => toggle_radius = true,
+                    "Shake" => shake = true,
+                    "Disk/Cloud" => toggle_scenario = true,
+                    "Soft Reset" => soft_reset = true,
+                    "Orbit" => {
+                        self.orbit_builder = match self.orbit_builder {
+                            OrbitBuilder::Off => OrbitBuilder::PickStar,
+                            _ => OrbitBuilder::Off,
+                        };
+                    }
+                    _ => (),
+                }
+                }
+            }
+
+            if let Some(index) = self.sliders.iter().position(|slider| slider.input_contains(x, y, self.ui_scale)) {
+                self.focused_input = index;
+                return;
+            }
+            if let Some(index) = self.sliders.iter_mut().position(|slider| slider.handle_click(x, y, self.ui_scale)) {
+                self.dragging_slider = Some(index);
+                self.apply_slider(index);
+                return;
+            }
+        }
+        
+        if should_pause {
+            self.toggle_pause();
+        }
+        if clicked_reset {
+            self.reset();
+        }
+        if soft_reset {
+            self.soft_reset();
+        }
+        if toggle_scenario {
+            self.scenario = match self.scenario {
+                Scenario::AccretionDisk => Scenario::UniformCloud,
+                Scenario::UniformCloud => Scenario::AccretionDisk,
+            };
+            self.reset();
+        }
+        if toggle_radius {
+            self.toggle_radius_model();
+        }
+        if shake {
+            shake_velocities(&mut self.particles, self.sliders[16].value, self.is_3d, SHAKE_SEED + self.shakes);
+            self.shakes += 1;
+        }
+        if start_add_mass {
+            self.adding_mass = true;
+            return;
+        }
+
+        if let Some(index) = self.mass_list_hit(mouse_pos) {
+            self.selected = Some(index);
+            self.center_on(index, instant);
+            return;
+        }
+
+        if self.orbit_builder != OrbitBuilder::Off {
+            if !self.over_ui(mouse_pos) {
+                self.orbit_builder_click(mouse_pos);
+            }
+            return;
+        }
+
+        if self.adding_tracers {
+            if !self.over_ui(mouse_pos) {
+                self.drop_tracer(mouse_pos);
+            }
+            return;
+        }
+
+        // Handle mass placement or panning
+        if self.adding_mass {
+            if !self.over_ui(mouse_pos) { // Don't add mass under the UI
+                self.add_large_mass(x, y);
+                self.adding_mass = false;
+                self.mass_preview = None;
+            }
+        } else {
+            // Select a particle, or start panning if not clicking UI
+            if !self.over_ui(mouse_pos) {
+                if let Some(index) = self.particle_at(mouse_pos) {
+                    self.selected = Some(index);
+                } else {
+                    self.is_panning = true;
+                    self.last_mouse_pos = mouse_pos;
+                }
+            }
+        }
+    }
+
+    fn mass_list_rect(&self) -> graphics::Rect {
+        // Sits beside the top of the slider column; below it there's no room left
+        let scale = self.ui_scale;
+        let height = MASS_LIST_ROW_HEIGHT * (MASS_LIST_ROWS + 1) as f32;
+        graphics::Rect::new((UI_PANEL_WIDTH + 10.0) * scale, (UI_STRIP_HEIGHT + 10.0) * scale, MASS_LIST_WIDTH * scale, height * scale)
+    }
+
+    // The slider column below the button strip
+    fn ui_panel_rect(&self) -> graphics::Rect {
+        let scale = self.ui_scale;
+        let bottom = self.sliders.last().map_or(50.0, |slider| slider.y_pos + 20.0) * scale;
+        graphics::Rect::new(0.0, UI_STRIP_HEIGHT * scale, UI_PANEL_WIDTH * scale, bottom - UI_STRIP_HEIGHT * scale)
+    }
+
+    // Whether a click at `point` belongs to the UI overlay rather than the world
+    fn over_ui(&self, point: Point2<f32>) -> bool {
+        let panel = self.panel_blocks_clicks.then(|| self.ui_panel_rect());
+        ui_blocks_point(point, self.ui_scale, panel) || (self.show_mass_list && self.mass_list_rect().contains(point))
+    }
+
+    // The particle whose row in the mass list is under `point`, if the list is open
+    fn mass_list_hit(&self, point: Point2<f32>) -> Option<usize> {
+        let rect = self.mass_list_rect();
+        if !self.show_mass_list || !rect.contains(point) {
+            return None;
+        }
+        // The first row is the header
+        let row = ((point.y - rect.y) / (MASS_LIST_ROW_HEIGHT * self.ui_scale)) as usize;
+        let entry = row.checked_sub(1)?;
+        self.mass_ranking
+            .get(self.mass_list_scroll + entry)
+            .copied()
+            .filter(|&index| index < self.particles.len())
+    }
+
+    // Rebuilds the surface density profile around the center of mass every
+    // DENSITY_REFRESH frames; the star is left out so it doesn't swamp the
+    // innermost ring
+    fn refresh_density_profile(&mut self) {
+        self.density_profile_age += 1;
+        if self.density_profile_age < DENSITY_REFRESH && !self.density_profile.is_empty() {
+            return;
+        }
+        self.density_profile_age = 0;
+        let Some(center) = center_of_mass(&self.particles) else {
+            self.density_profile.clear();
+            return;
+        };
+        let masses = annulus_masses(&self.particles, center, DENSITY_BIN_WIDTH, DENSITY_BINS, self.star_index());
+        self.density_profile = surface_density(&masses, DENSITY_BIN_WIDTH);
+    }
+
+    // The star is left out: its own velocity is the disk's, not the flow's
+    fn refresh_flow_field(&mut self) {
+        self.flow_field_age += 1;
+        if self.flow_field_age < FLOW_REFRESH && !self.flow_field.is_empty() {
+            return;
+        }
+        self.flow_field_age = 0;
+        self.flow_field = mean_velocity_grid(&self.particles, FLOW_CELL, self.star_index());
+    }
+
+    // One arrow per velocity field cell, longer for faster flow
+    fn draw_flow_field(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let speed = |v: &Vector3<f32>| (v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
+        let fastest = self.flow_field.iter().map(|(_, velocity)| speed(velocity)).fold(0.0, f32::max);
+        if fastest <= 0.0 {
+            return Ok(());
+        }
+        for (center, velocity) in &self.flow_field {
+            let magnitude = speed(velocity);
+            if magnitude <= 0.0 {
+                continue;
+            }
+            let from = self.world_to_screen(center);
+            let toward = self.world_to_screen(&Point3 {
+                x: center.x + velocity.x / magnitude,
+                y: center.y + velocity.y / magnitude,
+                z: center.z + velocity.z / magnitude,
+            });
+            let to = arrow_tip(from, toward, 6.0 + 14.0 * magnitude / fastest);
+            draw_arrow(ctx, canvas, from, to, Color::new(0.5, 0.7, 1.0, 0.35))?;
+        }
+        Ok(())
+    }
+
+    // Bar chart of the density profile, innermost ring on the left
+    fn draw_density_profile(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, panel: graphics::Rect) -> GameResult {
+        let background = Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), panel, Color::new(0.1, 0.1, 0.1, 0.8))?;
+        canvas.draw(&background, DrawParam::default());
+        let max = self.density_profile.iter().copied().fold(0.0, f32::max);
+        let label = format!("Surface density (0 - {:.0} units, max {:.4})", DENSITY_BIN_WIDTH * DENSITY_BINS as f32, max);
+        canvas.draw(&Text::new(label), DrawParam::default().dest([panel.x + 5.0, panel.y + 3.0]).color(Color::WHITE));
+        if max <= 0.0 {
+            return Ok(());
+        }
+        let plot_top = panel.y + 22.0;
+        let plot_height = panel.h - 27.0;
+        let bar_width = (panel.w - 10.0) / self.density_profile.len() as f32;
+        let mut builder = graphics::MeshBuilder::new();
+        for (i, density) in self.density_profile.iter().enumerate() {
+            let height = plot_height * density / max;
+            if height < 0.5 {
+                continue;
+            }
+            let bar = graphics::Rect::new(panel.x + 5.0 + bar_width * i as f32, plot_top + plot_height - height, bar_width - 1.0, height);
+            builder.rectangle(graphics::DrawMode::fill(), bar, Color::from_rgb(120, 180, 255))?;
+        }
+        canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::default());
+        Ok(())
+    }
+
+    fn refresh_mass_ranking(&mut self) {
+        self.mass_ranking_age += 1;
+        if self.mass_ranking_age >= MASS_LIST_REFRESH || self.mass_ranking.len() != self.particles.len() {
+            self.mass_ranking = rank_by_mass(&self.particles);
+            self.mass_ranking_age = 0;
+            self.mass_list_scroll = self.mass_list_scroll.min(self.mass_ranking.len().saturating_sub(MASS_LIST_ROWS));
+        }
+    }
+
+    // Moves the camera, either at once or as a short eased flight
+    fn move_camera(&mut self, zoom: f32, pan: Point2<f32>, instant: bool) {
+        if instant {
+            self.zoom = zoom;
+            self.pan = pan;
+            self.camera_flight = None;
+        } else {
+            self.camera_flight = Some(CameraFlight { from_zoom: self.zoom, from_pan: self.pan, to_zoom: zoom, to_pan: pan, elapsed: 0.0 });
+        }
+    }
+
+    fn save_bookmark(&mut self, slot: usize) {
+        self.bookmarks[slot] = Some((self.pan, self.zoom));
+        match std::fs::write(BOOKMARKS_FILE, format_bookmarks(&self.bookmarks)) {
+            Ok(()) => println!("Saved view to bookmark {}", slot + 1),
+            Err(err) => eprintln!("Saved bookmark {} for this run only, can't write {}: {}", slot + 1, BOOKMARKS_FILE, err),
+        }
+    }
+
+    fn recall_bookmark(&mut self, slot: usize, instant: bool) {
+        if let Some((pan, zoom)) = self.bookmarks[slot] {
+            self.move_camera(zoom, pan, instant);
+        }
+    }
+
+    // Pans so the particle sits in the middle of the window
+    fn center_on(&mut self, index: usize, instant: bool) {
+        let position = self.particles[index].position;
+        let pan = Point2 {
+            x: WINDOW_WIDTH / 2.0 / self.zoom - position.x,
+            y: WINDOW_HEIGHT / 2.0 / self.zoom - position.y,
+        };
+        self.move_camera(self.zoom, pan, instant);
+    }
+
+    // Zooms and pans so every particle is on screen with a small margin
+    fn fit_view(&mut self, instant: bool) {
+        let Some(first) = self.particles.first() else {
+            return;
+        };
+        let start = (first.position.x, first.position.x, first.position.y, first.position.y);
+        let (left, right, top, bottom) = self.particles.iter().fold(start, |(l, r, t, b), p| {
+            (l.min(p.position.x - p.radius), r.max(p.position.x + p.radius), t.min(p.position.y - p.radius), b.max(p.position.y + p.radius))
+        });
+        let (width, height) = ((right - left).max(100.0), (bottom - top).max(100.0));
+        let zoom = (WINDOW_WIDTH / width).min(WINDOW_HEIGHT / height) * 0.9;
+        let pan = Point2 {
+            x: WINDOW_WIDTH / 2.0 / zoom - (left + right) / 2.0,
+            y: WINDOW_HEIGHT / 2.0 / zoom - (top + bottom) / 2.0,
+        };
+        self.move_camera(zoom, pan, instant);
+    }
+
+    fn draw_mass_list(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult {
+        let rect = self.mass_list_rect();
+        let background = Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), rect, Color::new(0.0, 0.0, 0.0, 0.7))?;
+        canvas.draw(&background, DrawParam::default());
+        let row_height = MASS_LIST_ROW_HEIGHT * self.ui_scale;
+        let header = ui_text(format!("By mass ({} bodies)", self.mass_ranking.len()), self.ui_scale);
+        canvas.draw(&header, DrawParam::default().dest([rect.x + 4.0, rect.y + 2.0]).color(Color::WHITE));
+        let visible = self.mass_ranking.iter().skip(self.mass_list_scroll).take(MASS_LIST_ROWS);
+        for (row, &index) in visible.enumerate() {
+            let Some(particle) = self.particles.get(index) else {
+                continue;
+            };
+            let speed = (particle.velocity.x * particle.velocity.x + particle.velocity.y * particle.velocity.y).sqrt();
+            let line = format!(
+                "{:>3}. #{:<4} m {:>8.1}  v {:>6.2}",
+                self.mass_list_scroll + row + 1,
+                index,
+                particle.mass,
+                speed
+            );
+            let color = if Some(index) == self.selected { Color::YELLOW } else { Color::new(0.8, 0.8, 0.8, 1.0) };
+            let y = rect.y + row_height * (row + 1) as f32 + 2.0;
+            canvas.draw(&ui_text(line, self.ui_scale), DrawParam::default().dest([rect.x + 4.0, y]).color(color));
+        }
+        Ok(())
+    }
+
+    // Massless tracer on a circular orbit about the star, so it follows the disk's flow
+    fn drop_tracer(&mut self, mouse_pos: Point2<f32>) {
+        let position = self.screen_to_world(mouse_pos);
+        let mut tracer = Particle::new(position.x, position.y, 0.0, 0.0);
+        tracer.tracer = true;
+        if let Some(star) = self.star_index().map(|i| &self.particles[i]) {
+            let (dx, dy) = (position.x - star.position.x, position.y - star.position.y);
+            let distance = (dx * dx + dy * dy).sqrt();
+            if distance > f32::EPSILON {
+                let speed = orbit_velocity(star.mass, 0.0, distance, 0.0);
+                tracer.velocity = Vector3 {
+                    x: star.velocity.x - speed * dy / distance,
+                    y: star.velocity.y + speed * dx / distance,
+                    z: star.velocity.z,
+                };
+            }
+        }
+        self.particles.push(tracer);
+    }
+
+    // First click picks (or places) the star, second places a planet on an
+    // orbit around it with the eccentricity from the slider
+    fn orbit_builder_click(&mut self, mouse_pos: Point2<f32>) {
+        match self.orbit_builder {
+            OrbitBuilder::Off => {}
+            OrbitBuilder::PickStar => {
+                let star = self.particle_at(mouse_pos).unwrap_or_else(|| {
+                    let position = self.screen_to_world(mouse_pos);
+                    self.particles.push(self.new_particle(position.x, position.y, 0.0, self.sliders[3].value * 100.0));
+                    self.particles.len() - 1
+                });
+                self.selected = Some(star);
+                self.orbit_builder = OrbitBuilder::PlacePlanet;
+            }
+            OrbitBuilder::PlacePlanet => {
+                // The star can vanish (merged or deleted) between the clicks
+                let Some(star_index) = self.selected else {
+                    self.orbit_builder = OrbitBuilder::PickStar;
+                    return;
+                };
+                let star = self.particles[star_index].clone();
+                let position = self.screen_to_world(mouse_pos);
+                let (dx, dy) = (position.x - star.position.x, position.y - star.position.y);
+                let separation = (dx * dx + dy * dy).sqrt();
+                if separation <= star.radius {
+                    return;
+                }
+
+                let planet_mass = self.sliders[3].value;
+                let speed = orbit_velocity(star.mass, planet_mass, separation, self.sliders[12].value);
+                // Counterclockwise, like the orbits from reset
+                let (ux, uy) = (-dy / separation, dx / separation);
+                // Split the relative velocity so the pair's center of mass keeps the star's velocity
+                let total = star.mass + planet_mass;
+                let mut planet = self.new_particle(position.x, position.y, 0.0, planet_mass);
+                planet.velocity = Vector3 {
+                    x: star.velocity.x + ux * speed * star.mass / total,
+                    y: star.velocity.y + uy * speed * star.mass / total,
+                    z: 0.0,
+                };
+                let star = &mut self.particles[star_index];
+                star.velocity.x -= ux * speed * planet_mass / total;
+                star.velocity.y -= uy * speed * planet_mass / total;
+                self.particles.push(planet);
+                self.selected = Some(self.particles.len() - 1);
+                self.orbit_builder = OrbitBuilder::Off;
+            }
+        }
+    }
+
+    // Pushes a slider's value into the state it controls
+    fn apply_slider(&mut self, index: usize) {
+        let value = self.sliders[index].value;
+        match self.sliders[index].label.as_str() {
+            "Particles" => {
+                let count = value as usize;
+                if count != self.particle_count {
+                    self.particle_count = count;
+                    if self.live_particle_count {
+                        self.apply_particle_count();
+                    }
+                }
+            }
+            "Velocity" => self.initial_velocity_multiplier = value,
+            "Mass" => self.initial_mass_range = (value * 0.5, value * 1.5),
+            _ => (),
+        }
+    }
+
+    // Offered after a collapse: raise Velocity so orbits survive, then start over
+    fn reset_with_more_velocity(&mut self) {
+        let slider = &mut self.sliders[2];
+        slider.value = (slider.value * COLLAPSE_VELOCITY_BOOST).min(slider.max);
+        self.apply_slider(2);
+        self.reset();
+    }
+
+    fn handle_mouse_release(&mut self) {
+        for button in &mut self.buttons {
+            button.clicked = false;
+        }
+        self.is_panning = false;
+        self.dragging_slider = None;
+    }
+
+    fn handle_mouse_motion(&mut self, x: f32, y: f32) {
+        let current_pos = Point2 { x, y };
+        if self.show_cursor_escape {
+            let world = self.screen_to_world(current_pos);
+            self.cursor_escape = strongest_pull_at(&self.particles, world)
+                .map(|(body, distance)| (body, escape_velocity(self.particles[body].mass, distance)));
+        }
+
+        if let Some(index) = self.dragging_slider {
+            self.sliders[index].set_from_x(x, self.ui_scale);
+            self.apply_slider(index);
+        }
+        
+        if self.is_panning {
+            if self.is_3d {
+                self.rotation_y += (current_pos.x - self.last_mouse_pos.x) * 0.01;
+                self.rotation_x += (current_pos.y - self.last_mouse_pos.y) * 0.01;
+            } else {
+            self.camera_flight = None;
+            // Undo the view rotation so the world follows the mouse
+            let origin = Point2 { x: 0.0, y: 0.0 };
+            let drag = Point2 { x: current_pos.x - self.last_mouse_pos.x, y: current_pos.y - self.last_mouse_pos.y };
+            let drag = rotate_about(drag, origin, -self.view_angle);
+            self.pan.x += drag.x / self.zoom;
+            self.pan.y += drag.y / self.zoom;
+            }
+            self.last_mouse_pos = current_pos;
+        }
+
+        if self.adding_mass {
+            self.mass_preview = Some(current_pos);
+        }
+    }
+
+}
+
+// Update draw() to handle 3D projection:
+impl EventHandler for SimulationState {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        if !self.paused && self.smooth_motion {
+            // Physics ticks at a fixed rate and draw() blends between ticks;
+            // the cap stops a slow frame from snowballing
+            self.physics_clock += ctx.time.delta().as_secs_f32();
+            let mut ticks = 0;
+            while self.physics_clock >= SMOOTH_TICK && ticks < 4 {
+                self.advance_frame();
+                self.physics_clock -= SMOOTH_TICK;
+                ticks += 1;
+            }
+            self.physics_clock = self.physics_clock.min(SMOOTH_TICK);
+        } else if !self.paused {
+            self.advance_frame();
+        }
+        if self.show_mass_list {
+            self.refresh_mass_ranking();
+        }
+        if self.show_density_profile {
+            self.refresh_density_profile();
+        }
+        if self.show_flow {
+            self.refresh_flow_field();
+        }
+        if self.rest_frame && self.selected.is_none() {
+            self.rest_frame = false;
+            println!("Left the rest frame: the observed particle is gone");
+        }
+        // Cosmetic animation runs on wall-clock time, paused or not
+        self.animate(ctx.time.delta().as_secs_f32());
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let render_start = Instant::now();
+        let mut canvas = graphics::Canvas::from_frame(ctx, Color::BLACK);
+
+        // Draw the parallax starfield, wrapping so it tiles as the view pans
+        if self.show_starfield {
+            let offset = parallax_offset(self.pan);
+            // Heavy bodies bend the background around them (purely cosmetic)
+            let lenses: Vec<(Point2<f32>, f32)> = if self.show_lensing {
+                self.particles
+                    .iter()
+                    .filter(|particle| particle.mass >= LENSING_MIN_MASS)
+                    .map(|particle| (self.world_to_screen(&particle.position), LENSING_SCALE * (particle.mass * self.zoom).sqrt()))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let mut builder = graphics::MeshBuilder::new();
+            for star in &self.starfield {
+                let mut x = (star.position.x + offset.x).rem_euclid(WINDOW_WIDTH);
+                let mut y = (star.position.y + offset.y).rem_euclid(WINDOW_HEIGHT);
+                let (base_x, base_y) = (x, y);
+                for (lens, einstein_radius) in &lenses {
+                    let (dx, dy) = (base_x - lens.x, base_y - lens.y);
+                    let distance = (dx * dx + dy * dy).sqrt();
+                    if distance > f32::EPSILON {
+                        let push = lensing_displacement(*einstein_radius, distance) / distance;
+                        x += dx * push;
+                        y += dy * push;
+                    }
+                }
+                builder.rectangle(
+                    graphics::DrawMode::fill(),
+                    graphics::Rect::new(x, y, 1.5, 1.5),
+                    Color::new(1.0, 1.0, 1.0, star.brightness),
+                )?;
+            }
+            let stars = Mesh::from_data(ctx, builder.build());
+            canvas.draw(&stars, DrawParam::default());
+        }
+    
+        if self.show_flow {
+            self.draw_flow_field(ctx, &mut canvas)?;
+        }
+
+        // Faint frame around the world bounds, solid when the edges are active
+        let bounds = self.world_bounds;
+        let corners: Vec<Point2<f32>> = [
+            (bounds.left(), bounds.top()),
+            (bounds.right(), bounds.top()),
+            (bounds.right(), bounds.bottom()),
+            (bounds.left(), bounds.bottom()),
+        ]
+        .iter()
+        .map(|&(x, y)| self.world_to_screen(&Point3 { x, y, z: 0.0 }))
+        .collect();
+        let alpha = if self.boundary_mode == BoundaryMode::Open { 0.15 } else { 0.4 };
+        let frame = Mesh::new_polygon(ctx, graphics::DrawMode::stroke(1.0), &corners, Color::new(0.5, 0.6, 1.0, alpha))?;
+        canvas.draw(&frame, DrawParam::default());
+
+        // Faint outlines where everything was in the ghosted snapshot
+        if let Some(ghost) = &self.ghost {
+            let mut builder = graphics::MeshBuilder::new();
+            for (position, radius) in ghost {
+                let pos = self.world_to_screen(position);
+                let radius = (radius * self.zoom).max(1.0);
+                builder.circle(graphics::DrawMode::stroke(1.0), pos, radius, self.render_quality.tolerance(), Color::new(0.4, 0.8, 1.0, 0.35))?;
+            }
+            if !ghost.is_empty() {
+                canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::default());
+            }
+        }
+
+        // Draw particles, every one an instance of the same unit circle
+        let attractor = match (self.isolate, self.selected) {
+            (true, Some(index)) => dominant_attractor(&self.particles, index),
+            _ => None,
+        };
+        let (instances, streaks) = self.particle_instances(attractor);
+        if !streaks.is_empty() {
+            let mut builder = graphics::MeshBuilder::new();
+            for (from, to, width, color) in streaks {
+                builder.line(&[from, to], width, color)?;
+            }
+            canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::default());
+        }
+        if self.particle_mesh.is_none() {
+            // Tolerance is relative to the unit radius, so keep it fine enough
+            // to stay round once scaled up
+            self.particle_mesh = Some(Mesh::new_circle(
+                ctx,
+                graphics::DrawMode::fill(),
+                Point2 { x: 0.0, y: 0.0 },
+                1.0,
+                self.render_quality.tolerance() * UNIT_CIRCLE_TOLERANCE_SCALE,
+                Color::WHITE,
+            )?);
+        }
+        let particle_instances = self
+            .particle_instances
+            .get_or_insert_with(|| graphics::InstanceArray::new(ctx, None));
+        particle_instances.set(instances);
+        if let Some(mesh) = &self.particle_mesh {
+            canvas.draw_instanced_mesh(mesh.clone(), particle_instances, DrawParam::default());
+        }
+
+        // Hill spheres of the bodies heavy enough to clear a lane for themselves
+        if let (true, Some(star)) = (self.show_hill_spheres, self.star_index()) {
+            let star_body = &self.particles[star];
+            let mut builder = graphics::MeshBuilder::new();
+            let mut any = false;
+            for (i, particle) in self.particles.iter().enumerate() {
+                if i == star || particle.mass < HILL_MIN_MASS {
+                    continue;
+                }
+                let dx = particle.position.x - star_body.position.x;
+                let dy = particle.position.y - star_body.position.y;
+                let dz = particle.position.z - star_body.position.z;
+                let radius = hill_radius(particle.mass, star_body.mass, (dx * dx + dy * dy + dz * dz).sqrt()) * self.zoom;
+                if radius < 2.0 {
+                    continue;
+                }
+                let pos = self.world_to_screen(&particle.interpolated_position(alpha));
+                builder.circle(graphics::DrawMode::stroke(1.0), pos, radius, self.render_quality.tolerance(), Color::new(0.5, 1.0, 0.5, 0.4))?;
+                any = true;
+            }
+            if any {
+                canvas.draw(&Mesh::from_data(ctx, builder.build()), DrawParam::default());
+            }
+        }
+
+        // A spoke turning at each visibly spinning body's rate
+        let mut spokes = graphics::MeshBuilder::new();
+        let mut any_spokes = false;
+        for particle in self.particles.iter().filter(|particle| particle.spin.abs() > SPIN_INDICATOR_MIN) {
+            let radius = particle.radius * self.zoom;
+            if radius < 3.0 {
+                continue;
+            }
+            let pos = self.world_to_screen(&particle.interpolated_position(alpha));
+            let angle = particle.spin * self.sim_time;
+            let tip = Point2 { x: pos.x + radius * angle.cos(), y: pos.y + radius * angle.sin() };
+            spokes.line(&[pos, tip], 1.0, Color::new(0.2, 0.2, 0.2, 0.8))?;
+            any_spokes = true;
+        }
+        if any_spokes {
+            canvas.draw(&Mesh::from_data(ctx, spokes.build()), DrawParam::default());
+        }
+
+        // Square outline marks the fixed anchors
+        for particle in self.particles.iter().filter(|particle| particle.fixed) {
+            let pos = self.world_to_screen(&particle.position);
+            let half = particle.radius * self.zoom + 3.0;
+            let square = Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::stroke(1.0),
+                graphics::Rect::new(pos.x - half, pos.y - half, half * 2.0, half * 2.0),
+                Color::CYAN,
+            )?;
+            canvas.draw(&square, DrawParam::default());
+        }
+
+        // Draw the quadtree cells, and optionally each node's center of mass
+        let mut quadtree_leaves = None;
+        if self.quadtree_overlay != QuadtreeOverlay::Off && !self.is_3d && !self.particles.is_empty() {
+            let tree = QuadNode::build(&self.particles);
+            quadtree_leaves = Some(tree.leaf_count());
+            let mut builder = graphics::MeshBuilder::new();
+            let mut result = Ok(());
+            tree.visit(&mut |node| {
+                if result.is_err() {
+                    return;
+                }
+                let b = node.bounds;
+                let corners = [(b.x, b.y), (b.x + b.w, b.y), (b.x + b.w, b.y + b.h), (b.x, b.y + b.h)]
+                    .map(|(x, y)| self.world_to_screen(&Point3 { x, y, z: 0.0 }));
+                result = builder
+                    .polygon(graphics::DrawMode::stroke(1.0), &corners, Color::new(0.2, 0.6, 1.0, 0.35))
+                    .map(|_| ());
+                if result.is_ok() && self.quadtree_overlay == QuadtreeOverlay::CellsAndMass && node.mass > 0.0 {
+                    let com = self.world_to_screen(&Point3 { x: node.center_of_mass.x, y: node.center_of_mass.y, z: 0.0 });
+                    result = builder
+                        .circle(graphics::DrawMode::fill(), com, 1.5, self.render_quality.tolerance(), Color::new(1.0, 0.3, 0.3, 0.8))
+                        .map(|_| ());
+                }
+            });
+            result?;
+            let overlay = Mesh::from_data(ctx, builder.build());
+            canvas.draw(&overlay, DrawParam::default());
+        }
+
+        // Draw the gravitational potential contours (2D only)
+        if self.show_contours && !self.is_3d && !self.particles.is_empty() {
+            let key = (self.steps, self.pan.x, self.pan.y, self.zoom, self.particles.len());
+            if self.contour_cache.as_ref().is_none_or(|(cached, _)| *cached != key) {
+                self.contour_cache = Some((key, self.potential_contours()));
+            }
+            if let Some((_, segments)) = &self.contour_cache {
+                let mut builder = graphics::MeshBuilder::new();
+                for [a, b] in segments {
+                    let from = self.world_to_screen(&Point3 { x: a.x, y: a.y, z: 0.0 });
+                    let to = self.world_to_screen(&Point3 { x: b.x, y: b.y, z: 0.0 });
+                    builder.line(&[from, to], 1.0, Color::new(0.4, 1.0, 0.6, 0.4))?;
+                }
+                if !segments.is_empty() {
+                    let contours = Mesh::from_data(ctx, builder.build());
+                    canvas.draw(&contours, DrawParam::default());
+                }
+            }
+        }
+
+        // Bracket each bound group found by the last scan
+        if self.show_binaries {
+            for group in &self.bound_groups {
+                let points: Vec<Point2<f32>> = group
+                    .iter()
+                    .filter_map(|&i| self.particles.get(i))
+                    .map(|particle| self.world_to_screen(&particle.position))
+                    .collect();
+                if points.len() < 2 {
+                    continue;
+                }
+                let min_x = points.iter().map(|p| p.x).fold(f32::MAX, f32::min) - 6.0;
+                let min_y = points.iter().map(|p| p.y).fold(f32::MAX, f32::min) - 6.0;
+                let max_x = points.iter().map(|p| p.x).fold(f32::MIN, f32::max) + 6.0;
+                let max_y = points.iter().map(|p| p.y).fold(f32::MIN, f32::max) + 6.0;
+                let bracket = Mesh::new_rectangle(
+                    ctx,
+                    graphics::DrawMode::stroke(1.0),
+                    graphics::Rect::new(min_x, min_y, max_x - min_x, max_y - min_y),
+                    Color::CYAN,
+                )?;
+                canvas.draw(&bracket, DrawParam::default());
+                let label = if points.len() == 2 { "binary".to_string() } else { format!("group of {}", points.len()) };
+                canvas.draw(&Text::new(label), DrawParam::default().dest([min_x, max_y + 2.0]).color(Color::CYAN));
+            }
+        }
+
+        // Label heavy particles with their mass; labels shrink and fade out as
+        // the view zooms away so they don't swamp the scene
+        if self.show_mass_labels {
+            let alpha = ((self.zoom - 0.2) / 0.8).clamp(0.0, 1.0);
+            if alpha > 0.0 {
+                let size = (14.0 * self.zoom).clamp(9.0, 18.0);
+                for i in mass_label_indices(&self.particles, MASS_LABEL_THRESHOLD) {
+                    let particle = &self.particles[i];
+                    let pos = self.world_to_screen(&particle.position);
+                    let offset = particle.radius * self.zoom + 3.0;
+                    let mut label = Text::new(format!("{:.0}", particle.mass));
+                    label.set_scale(size);
+                    canvas.draw(
+                        &label,
+                        DrawParam::default().dest([pos.x + offset, pos.y - offset]).color(Color::new(0.8, 0.9, 1.0, alpha)),
+                    );
+                }
+            }
+        }
+
+        // Circle around the center of mass reaching the farthest particle
+        if let (true, Some(center)) = (self.show_bounds, center_of_mass(&self.particles)) {
+            let radius = enclosing_radius(&self.particles, center);
+            let pos = self.world_to_screen(&center);
+            if radius * self.zoom > 1.0 {
+                let circle = Mesh::new_circle(
+                    ctx,
+                    graphics::DrawMode::stroke(1.0),
+                    pos,
+                    radius * self.zoom,
+                    0.5,
+                    Color::new(0.6, 1.0, 0.6, 0.5),
+                )?;
+                canvas.draw(&circle, DrawParam::default());
+            }
+            let units = self.units();
+            let label = ui_text(format!("R = {:.1} {}", units.distance(radius), units.distance_name), self.ui_scale);
+            canvas.draw(
+                &label,
+                DrawParam::default().dest([pos.x + 4.0, pos.y - radius * self.zoom - 20.0 * self.ui_scale]).color(Color::new(0.6, 1.0, 0.6, 0.8)),
+            );
+        }
+
+        // Draw merge ripples, expanding and fading over their lifetime
+        for effect in &self.merge_effects {
+            let progress = effect.age as f32 / MERGE_EFFECT_LIFETIME as f32;
+            let ripple = Mesh::new_circle(
+                ctx,
+                graphics::DrawMode::stroke(2.0),
+                self.world_to_screen(&effect.position),
+                (4.0 + 16.0 * progress) * self.zoom,
+                self.render_quality.tolerance(),
+                Color::new(1.0, 0.8, 0.3, 1.0 - progress),
+            )?;
+            canvas.draw(&ripple, DrawParam::default());
+        }
+
+        // Net pull on the star, the reason it wanders off center
+        if let (true, Some(star)) = (self.show_star_force, self.star_index()) {
+            let star = &self.particles[star];
+            let acceleration = star.acceleration;
+            let magnitude = (acceleration.x * acceleration.x + acceleration.y * acceleration.y + acceleration.z * acceleration.z).sqrt();
+            if magnitude > 0.0 {
+                let from = self.world_to_screen(&star.position);
+                let toward = self.world_to_screen(&Point3 {
+                    x: star.position.x + acceleration.x / magnitude,
+                    y: star.position.y + acceleration.y / magnitude,
+                    z: star.position.z + acceleration.z / magnitude,
+                });
+                let edge = self.star_display_radius * self.zoom;
+                let length = edge + 20.0 + 15.0 * (1.0 + magnitude * 100.0).log10();
+                let to = arrow_tip(from, toward, length);
+                draw_arrow(ctx, &mut canvas, arrow_tip(from, toward, edge), to, Color::new(1.0, 0.4, 0.4, 0.9))?;
+                let label = Text::new(format!("F = {:.2}", magnitude * star.mass));
+                canvas.draw(&label, DrawParam::default().dest([to.x + 4.0, to.y + 4.0]).color(Color::new(1.0, 0.4, 0.4, 0.9)));
+            }
+        }
+
+        // Path of the heaviest body, broken wherever the lead changed hands
+        if self.track_leader {
+            let mut stretch: Vec<Point2<f32>> = Vec::new();
+            for point in self.leader_path.iter().chain([None].iter()) {
+                match point {
+                    Some(position) => stretch.push(self.world_to_screen(position)),
+                    None => {
+                        if stretch.len() >= 2 {
+                            let line = Mesh::new_line(ctx, &stretch, 1.5, Color::new(1.0, 0.85, 0.3, 0.7))?;
+                            canvas.draw(&line, DrawParam::default());
+                        }
+                        stretch.clear();
+                    }
+                }
+            }
+        }
+
+        // Trails behind the tracked particles
+        for particle in &self.particles {
+            let Some(trail) = particle.trail.as_ref().filter(|trail| trail.len() >= 2) else {
+                continue;
+            };
+            let points: Vec<Point2<f32>> = trail.iter().map(|position| self.world_to_screen(position)).collect();
+            let mut color = particle.birth_color;
+            color.a = 0.6;
+            let line = Mesh::new_line(ctx, &points, 1.0, color)?;
+            canvas.draw(&line, DrawParam::default());
+        }
+
+        if self.show_orbits {
+            self.draw_orbits(ctx, &mut canvas)?;
+        }
+
+        // In a rest frame, the nearest bodies get arrows for their velocity
+        // relative to the observer
+        if let (true, Some(observer)) = (self.rest_frame, self.selected) {
+            let origin = &self.particles[observer];
+            let mut nearby: Vec<(f32, usize)> = self
+                .particles
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != observer)
+                .map(|(i, p)| {
+                    let (dx, dy, dz) = (p.position.x - origin.position.x, p.position.y - origin.position.y, p.position.z - origin.position.z);
+                    (dx * dx + dy * dy + dz * dz, i)
+                })
+                .collect();
+            nearby.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for &(_, i) in nearby.iter().take(REST_FRAME_ARROWS) {
+                let body = &self.particles[i];
+                let from = self.world_to_screen(&body.position);
+                let ahead = Point3 {
+                    x: body.position.x + (body.velocity.x - origin.velocity.x) * REST_FRAME_LOOKAHEAD,
+                    y: body.position.y + (body.velocity.y - origin.velocity.y) * REST_FRAME_LOOKAHEAD,
+                    z: body.position.z + (body.velocity.z - origin.velocity.z) * REST_FRAME_LOOKAHEAD,
+                };
+                let to = self.world_to_screen(&ahead);
+                if (to.x - from.x).abs() + (to.y - from.y).abs() > 2.0 {
+                    draw_arrow(ctx, &mut canvas, from, to, Color::new(0.5, 1.0, 0.8, 0.7))?;
+                }
+            }
+        }
+
+        // Highlight the selection and, in isolate mode, its dominant attractor
+        let now = self.anim_time;
+        if self.focus.map(|(index, _)| index) != self.selected {
+            self.focus = self.selected.map(|index| (index, now));
+        }
+        if let Some((selected, focused_at)) = self.focus {
+            let particle = &self.particles[selected];
+            let pos = self.world_to_screen(&particle.interpolated_position(alpha));
+            let ring = Mesh::new_circle(
+                ctx,
+                graphics::DrawMode::stroke(1.5),
+                pos,
+                particle.radius * self.zoom + focus_ring_gap(now - focused_at),
+                self.render_quality.tolerance(),
+                Color::YELLOW,
+            )?;
+            canvas.draw(&ring, DrawParam::default());
+
+            if let Some((index, force)) = attractor {
+                let other = self.world_to_screen(&self.particles[index].position);
+                let line = Mesh::new_line(ctx, &[pos, other], 1.0, Color::YELLOW)?;
+                canvas.draw(&line, DrawParam::default());
+                let label = Text::new(format!("F = {:.2}", force));
+                let midpoint = [(pos.x + other.x) / 2.0 + 5.0, (pos.y + other.y) / 2.0 + 5.0];
+                canvas.draw(&label, DrawParam::default().dest(midpoint).color(Color::YELLOW));
+            }
+
+            // Stretching arrows on the near and far side, along the star direction,
+            // with a length that grows logarithmically with the tidal strength
+            if let (true, Some(tidal), Some(star)) = (self.show_tidal, self.selected_tidal_acceleration(), self.star_index()) {
+                let star_pos = self.world_to_screen(&self.particles[star].position);
+                let (dx, dy) = (pos.x - star_pos.x, pos.y - star_pos.y);
+                let distance = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+                let (ux, uy) = (dx / distance, dy / distance);
+                let edge = particle.radius * self.zoom + 2.0;
+                let length = (8.0 + 6.0 * (1.0 + tidal * 1000.0).log10()).clamp(6.0, 40.0);
+                for sign in [1.0, -1.0] {
+                    let from = Point2 { x: pos.x + sign * ux * edge, y: pos.y + sign * uy * edge };
+                    let to = Point2 { x: from.x + sign * ux * length, y: from.y + sign * uy * length };
+                    draw_arrow(ctx, &mut canvas, from, to, Color::MAGENTA)?;
+                }
+            }
+        }
+    
+        // Draw mass preview
+        if self.adding_mass {
+            if let Some(pos) = self.mass_preview {
+                let preview_circle = Mesh::new_circle(
+                    ctx,
+                    graphics::DrawMode::stroke(2.0),
+                    pos,
+                    (self.sliders[3].value * 0.3).max(2.0),
+                    self.render_quality.tolerance(),
+                    Color::YELLOW,
+                )?;
+                canvas.draw(&preview_circle, DrawParam::default());
+            }
+        }
+    
+        // Draw UI elements
+        for button in &self.buttons {
+            button.draw(ctx, &mut canvas, self.ui_scale)?;
+        }
+    
+        // Backdrop marks the part of the panel that blocks clicks on the world
+        if self.panel_blocks_clicks {
+            let backdrop = Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                self.ui_panel_rect(),
+                Color::new(0.0, 0.0, 0.0, 0.45),
+            )?;
+            canvas.draw(&backdrop, DrawParam::default());
+        }
+        for slider in &self.sliders {
+            slider.draw(ctx, &mut canvas, self.ui_scale, self.render_quality.tolerance())?;
+        }
+        if self.live_particle_count {
+            let live_text = ui_text("live", self.ui_scale);
+            let dest = [490.0 * self.ui_scale, self.sliders[1].y_pos * self.ui_scale];
+            canvas.draw(&live_text, DrawParam::default().dest(dest).color(Color::GREEN));
+        }
+    
+        // HUD readouts, with the inspector stacked underneath
+        let units = self.units();
+        let mut hud_lines = vec![
+            format!("Steps: {}", self.steps),
+            format!("Time: {:.2} {}", units.time(self.sim_time), units.time_name),
+            format!("Seed: {}{}", self.seed, if self.freeze_seed { " (frozen)" } else { "" }),
+        ];
+        if self.lock_dt_power_of_two {
+            let dt = self.frame_dt();
+            hud_lines.push(format!("dt locked: 1/{}", (1.0 / dt).round()));
+        }
+        let max_mass = self.star_index().map_or(0.0, |star| self.particles[star].mass);
+        if likely_unstable(self.sliders[4].value, self.frame_dt(), max_mass) {
+            hud_lines.push("Unstable step: raise Softening".to_string());
+            hud_lines.push("or lower Time Step / Time Speed".to_string());
+        }
+        match self.boundary_mode {
+            BoundaryMode::Open => (),
+            BoundaryMode::Wrap => hud_lines.push("Edges: wrap".to_string()),
+            BoundaryMode::Bounce => hud_lines.push("Edges: bounce".to_string()),
+        }
+        if let (true, Some(observer)) = (self.rest_frame, self.selected) {
+            hud_lines.push(format!("Rest frame of #{}", observer));
+        }
+        if self.force_mode == ForceMode::BarnesHut {
+            hud_lines.push(if self.is_3d { "Barnes-Hut (off in 3D)" } else { "Barnes-Hut" }.to_string());
+        }
+        if self.particles.len() > safe_particle_limit(self.force_mode, self.safe_particles) {
+            hud_lines.push("Too many particles for".to_string());
+            hud_lines.push(match self.force_mode {
+                ForceMode::BruteForce => "brute force: press 1 or lower".to_string(),
+                ForceMode::BarnesHut => "smooth play: lower".to_string(),
+            });
+            hud_lines.push("the Particles slider".to_string());
+        }
+        if self.frame_skip > 1 {
+            hud_lines.push(format!("Fast-forward ×{}", self.frame_skip));
+        }
+        if let (true, Some((body, speed))) = (self.show_cursor_escape, self.cursor_escape) {
+            hud_lines.push(format!(
+                "Escape at cursor: {:.2} {} (from particle {})",
+                units.speed(speed),
+                units.speed_name(),
+                body
+            ));
+        }
+        if let (true, Some(leader)) = (self.track_leader, self.leader.filter(|&i| i < self.particles.len())) {
+            hud_lines.push(format!("Leader: particle {} (mass {:.1})", leader, self.particles[leader].mass));
+        }
+        if self.show_clusters {
+            let (count, largest) = self.cluster_summary;
+            hud_lines.push(format!("Clusters: {} (largest mass {:.1})", count, largest));
+        }
+        if let Some(phase) = self.phase {
+            hud_lines.push(format!("Phase: {}", phase.label()));
+        }
+        if self.show_drift {
+            let (energy, momentum, angular) = self.conserved_at_reset.drift(&Conserved::measure(&self.particles));
+            hud_lines.push("Drift since reset:".to_string());
+            hud_lines.push(format!("  Energy: {:+.3}%", energy));
+            hud_lines.push(format!("  Momentum: {:.3}%", momentum));
+            hud_lines.push(format!("  Ang. momentum: {:+.3}%", angular));
+        }
+        let lifetime = self.sliders[21].value as u32;
+        if lifetime > 0 {
+            let fate = if self.recycle_expired { "recycled" } else { "removed" };
+            hud_lines.push(format!("Lifetime: {} steps, then {}", lifetime, fate));
+        }
+        if self.scenario == Scenario::UniformCloud {
+            hud_lines.push("Scenario: uniform cloud".to_string());
+        }
+        if self.double_precision {
+            hud_lines.push("Physics: f64, direct sum".to_string());
+        }
+        if self.size_softening {
+            hud_lines.push("Softening: by body size".to_string());
+        }
+        if self.render_quality != RenderQuality::Medium {
+            hud_lines.push(format!("Circle quality: {:?}", self.render_quality));
+        }
+        if self.view_angle != 0.0 || self.north_up {
+            hud_lines.push(format!("View rotation: {:.0}°{}", self.view_angle.to_degrees(), if self.north_up { " (north up)" } else { "" }));
+        }
+        if self.smooth_motion {
+            hud_lines.push(format!("Smooth motion: {:.0} ticks/s", 1.0 / SMOOTH_TICK));
+        }
+        if self.show_timings {
+            hud_lines.push(format!("Forces: {:.2} ms", self.force_timing.average_ms));
+            hud_lines.push(format!("Collisions: {:.2} ms", self.collision_timing.average_ms));
+            hud_lines.push(format!("Render: {:.2} ms", self.render_timing.average_ms));
+            hud_lines.push(format!("Frames: {} ({:.1} steps each)", self.rendered_frames, self.steps as f32 / self.rendered_frames.max(1) as f32));
+        }
+        if let RadiusModel::Fixed(radius) = self.radius_model {
+            hud_lines.push(format!("Fixed radius: {:.1}", radius));
+        }
+        if !self.gravity_enabled {
+            hud_lines.push("Gravity off (free drift)".to_string());
+        }
+        if self.recording {
+            hud_lines.push(format!("REC frame {}", self.recorded_frames));
+        }
+        if self.particles.is_empty() {
+            hud_lines.push("No particles left\nPress R to reset".to_string());
+        }
+        if let Some(leaves) = quadtree_leaves {
+            hud_lines.push(format!("Quadtree leaves: {}", leaves));
+        }
+        if self.show_binaries {
+            let binaries = self.bound_groups.iter().filter(|group| group.len() == 2).count();
+            hud_lines.push(format!("Binaries: {} (groups: {})", binaries, self.bound_groups.len()));
+        }
+        if self.force_interval() > 1 {
+            hud_lines.push(format!("Forces every {} steps\n(reduced accuracy)", self.force_interval()));
+        }
+        let hud_x = WINDOW_WIDTH - 220.0 * self.ui_scale;
+        let hud = ui_text(hud_lines.join("\n"), self.ui_scale);
+        let hud_height = hud.measure(ctx)?.y;
+        canvas.draw(&hud, DrawParam::default().dest([hud_x, 60.0 * self.ui_scale]).color(Color::WHITE));
+
+        // Inspector for the selected particle
+        if let Some(particle) = self.selected.and_then(|i| self.particles.get(i)) {
+            let speed = (particle.velocity.x * particle.velocity.x + particle.velocity.y * particle.velocity.y).sqrt();
+            let mut info = format!(
+                "Selected\nmass: {:.2}\npos: ({:.1}, {:.1}) {}\nvel: ({:.2}, {:.2})\nspeed: {:.3} {}",
+                particle.mass,
+                units.distance(particle.position.x),
+                units.distance(particle.position.y),
+                units.distance_name,
+                particle.velocity.x,
+                particle.velocity.y,
+                units.speed(speed),
+                units.speed_name(),
+            );
+            if let Some(star) = self.star_index().filter(|&star| Some(star) != self.selected) {
+                let star = &self.particles[star].position;
+                let (dx, dy) = (particle.position.x - star.x, particle.position.y - star.y);
+                let distance = (dx * dx + dy * dy).sqrt();
+                info.push_str(&format!("\nfrom star: {:.3} {}", units.distance(distance), units.distance_name));
+            }
+            if particle.fixed {
+                info.push_str("\nfixed anchor");
+            }
+            match self.period_tracker.as_ref().filter(|tracker| Some(tracker.index) == self.selected) {
+                Some(PeriodTracker { period: Some(period), .. }) => {
+                    info.push_str(&format!("\nperiod: {:.2} {}", units.time(*period), units.time_name));
+                }
+                Some(_) => info.push_str("\nperiod: measuring..."),
+                None => (),
+            }
+            if self.show_tidal {
+                if let Some(tidal) = self.selected_tidal_acceleration() {
+                    info.push_str(&format!("\ntidal: {:.4}", tidal));
+                }
+            }
+            let text = ui_text(info, self.ui_scale);
+            let dest = [hud_x, (80.0 * self.ui_scale) + hud_height];
+            canvas.draw(&text, DrawParam::default().dest(dest).color(Color::WHITE));
+        }
+
+        if self.show_mass_list {
+            self.draw_mass_list(ctx, &mut canvas)?;
+        }
+
+        if self.show_density_profile {
+            let panel = graphics::Rect::new(WINDOW_WIDTH - 330.0, WINDOW_HEIGHT - 300.0, 320.0, 140.0);
+            self.draw_density_profile(ctx, &mut canvas, panel)?;
+        }
+
+        if let Some((cursor, sign)) = self.gun {
+            let color = if sign > 0.0 { Color::new(0.4, 0.8, 1.0, 0.7) } else { Color::new(1.0, 0.5, 0.3, 0.7) };
+            let ring = graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(2.0), cursor, (GUN_SOFTENING * self.zoom).max(4.0), 0.5, color)?;
+            canvas.draw(&ring, DrawParam::default());
+        }
+
+        // Which way world "up" points once the view is rotated
+        if self.show_compass {
+            let center = Point2 { x: WINDOW_WIDTH - 60.0, y: 60.0 };
+            let tip = rotate_about(Point2 { x: center.x, y: center.y - 35.0 }, center, self.view_angle);
+            let ring = graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(1.0), center, 40.0, 0.5, Color::new(1.0, 1.0, 1.0, 0.4))?;
+            canvas.draw(&ring, DrawParam::default());
+            draw_arrow(ctx, &mut canvas, center, tip, Color::new(1.0, 0.4, 0.4, 0.9))?;
+            let label = Text::new(format!("N {:.0}°{}", self.view_angle.to_degrees(), if self.north_up { " locked" } else { "" }));
+            canvas.draw(&label, DrawParam::default().dest([center.x - 40.0, center.y + 46.0]).color(Color::WHITE));
+        }
+
+        if self.show_accretion_plot {
+            let panel = graphics::Rect::new(WINDOW_WIDTH - 330.0, WINDOW_HEIGHT - 150.0, 320.0, 140.0);
+            self.draw_history_plot(ctx, &mut canvas, &self.star_mass_history, panel, "Star mass")?;
+        }
+
+        if self.collapsed {
+            let message = ui_text(
+                "Almost everything fell into the star. Press U to reset with higher Velocity.",
+                self.ui_scale,
+            );
+            let width = message.measure(ctx)?.x;
+            let dest = [(WINDOW_WIDTH - width) / 2.0, WINDOW_HEIGHT / 2.0 - 60.0 * self.ui_scale];
+            canvas.draw(&message, DrawParam::default().dest(dest).color(Color::from_rgb(255, 160, 80)));
+        }
+
+        // Draw mode indicator
+        let mode_text = if self.adding_mass {
+            "Click to place mass"
+        } else if self.adding_tracers {
+            "Click to drop tracers, 3 to stop"
+        } else if self.orbit_builder == OrbitBuilder::PickStar {
+            "Orbit builder: click a body or empty space for the star"
+        } else if self.orbit_builder == OrbitBuilder::PlacePlanet {
+            "Orbit builder: click to place the planet"
+        } else if self.is_3d {
+            "Click and drag to rotate"
+        } else {
+            "Click and drag to pan"
+        };
+        let text = ui_text(mode_text, self.ui_scale);
+        let mode_x = self.buttons.last().map_or(0.0, |button| button.rect.right()) + 20.0;
+        canvas.draw(&text, DrawParam::default().dest([mode_x * self.ui_scale, 15.0 * self.ui_scale]).color(Color::WHITE));
+    
+        canvas.finish(ctx)?;
+        self.render_timing.record(render_start.elapsed());
+        if self.recording {
+            self.record_frame(ctx);
+        }
+        Ok(())
+    }
+
+    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) -> GameResult {
+        if let Some((cursor, _)) = self.gun.as_mut() {
+            *cursor = Point2 { x, y };
+        }
+        self.handle_mouse_motion(x, y);
+        Ok(())
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) -> GameResult {
+        if button == MouseButton::Left {
+            self.handle_mouse_click(x, y, ctx.keyboard.is_mod_active(KeyMods::SHIFT));
+        }
+        // Holding the right button turns the cursor into an attractor, or a
+        // repeller with Shift
+        if button == MouseButton::Right && !self.over_ui(Point2 { x, y }) {
+            let sign = if ctx.keyboard.is_mod_active(KeyMods::SHIFT) { -1.0 } else { 1.0 };
+            self.gun = Some((Point2 { x, y }, sign));
+        }
+        Ok(())
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) -> GameResult {
+        if button == MouseButton::Left {
+            self.handle_mouse_release();
+        }
+        if button == MouseButton::Right {
+            self.gun = None;
+        }
+        Ok(())
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+        // Over the mass list the wheel scrolls it instead of zooming
+        if self.show_mass_list && self.mass_list_rect().contains(ctx.mouse.position()) {
+            let last = self.mass_ranking.len().saturating_sub(MASS_LIST_ROWS);
+            self.mass_list_scroll = if y > 0.0 {
+                self.mass_list_scroll.saturating_sub(1)
+            } else {
+                (self.mass_list_scroll + 1).min(last)
+            };
+            return Ok(());
+        }
+        self.camera_flight = None;
+        self.zoom *= zoom_factor(y, self.zoom_step, self.smooth_zoom);
+        Ok(())
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeat: bool) -> GameResult {
+        // Manual panning takes over from any camera flight
+        if matches!(input.keycode, Some(KeyCode::W | KeyCode::A | KeyCode::S | KeyCode::D)) {
+            self.camera_flight = None;
+        }
+        // Ctrl+digit saves a camera bookmark, Alt+digit flies back to it
+        let slot = match input.keycode {
+            Some(KeyCode::Key1) => Some(0),
+            Some(KeyCode::Key2) => Some(1),
+            Some(KeyCode::Key3) => Some(2),
+            Some(KeyCode::Key4) => Some(3),
+            Some(KeyCode::Key5) => Some(4),
+            Some(KeyCode::Key6) => Some(5),
+            Some(KeyCode::Key7) => Some(6),
+            Some(KeyCode::Key8) => Some(7),
+            Some(KeyCode::Key9) => Some(8),
+            _ => None,
+        };
+        if let Some(slot) = slot {
+            if input.mods.contains(KeyMods::CTRL) {
+                self.save_bookmark(slot);
+                return Ok(());
+            }
+            if input.mods.contains(KeyMods::ALT) {
+                self.recall_bookmark(slot, input.mods.contains(KeyMods::SHIFT));
+                return Ok(());
+            }
+        }
+
+        // Shift+arrows nudge the selection's position, Ctrl+arrows its velocity
+        let nudge = match input.keycode {
+            Some(KeyCode::Up) => Some((0.0, -1.0)),
+            Some(KeyCode::Down) => Some((0.0, 1.0)),
+            Some(KeyCode::Left) => Some((-1.0, 0.0)),
+            Some(KeyCode::Right) => Some((1.0, 0.0)),
+            _ => None,
+        };
+        if let Some((dx, dy)) = nudge {
+            if input.mods.contains(KeyMods::SHIFT) {
+                self.nudge_selected(dx, dy, false);
+            } else if input.mods.contains(KeyMods::CTRL) {
+                self.nudge_selected(dx, dy, true);
+            }
+        }
+
+        match input.keycode {
+            Some(KeyCode::Space) => self.toggle_pause(),
+            // Shift+Z overlays the last pause's snapshot instead of restoring it
+            Some(KeyCode::Z) if input.mods.contains(KeyMods::SHIFT) => {
+                self.ghost = match (&self.ghost, &self.pause_snapshot) {
+                    (None, Some(snapshot)) => Some(snapshot.ghost()),
+                    _ => None,
+                };
+            }
+            Some(KeyCode::Z) => self.restore_last_pause(),
+            Some(KeyCode::N) => self.toggle_stats_log(),
+            Some(KeyCode::V) if input.mods.contains(KeyMods::SHIFT) => {
+                self.show_flow = !self.show_flow;
+                self.flow_field.clear();
+            }
+            Some(KeyCode::V) => self.show_birth_colors = !self.show_birth_colors,
+            Some(KeyCode::U) if self.collapsed => self.reset_with_more_velocity(),
+            Some(KeyCode::R) => self.reset(),
+            Some(KeyCode::I) => self.isolate = !self.isolate,
+            Some(KeyCode::B) => self.show_starfield = !self.show_starfield,
+            Some(KeyCode::T) => self.tilted = !self.tilted,
+            Some(KeyCode::J) if input.mods.contains(KeyMods::SHIFT) => {
+                self.show_clusters = !self.show_clusters;
+                if self.show_clusters {
+                    self.scan_clusters();
+                }
+            }
+            Some(KeyCode::J) => {
+                self.show_binaries = !self.show_binaries;
+                if self.show_binaries {
+                    let star = self.star_index().unwrap_or(usize::MAX);
+                    self.bound_groups = find_bound_groups(&self.particles, star);
+                }
+            }
+            Some(KeyCode::K) => self.kick_star(self.kick_from_sliders()),
+            Some(KeyCode::X) => self.clear_to_star(),
+            Some(KeyCode::P) => self.show_contours = !self.show_contours,
+            Some(KeyCode::M) => self.show_mass_labels = !self.show_mass_labels,
+            Some(KeyCode::H) => self.show_accretion_plot = !self.show_accretion_plot,
+            Some(KeyCode::E) => self.show_tidal = !self.show_tidal,
+            Some(KeyCode::G) => self.gravity_enabled = !self.gravity_enabled,
+            Some(KeyCode::F2) => self.lock_dt_power_of_two = !self.lock_dt_power_of_two,
+            Some(KeyCode::F3) => self.use_real_units = !self.use_real_units,
+            Some(KeyCode::F4) => self.show_timings = !self.show_timings,
+            Some(KeyCode::F5) => self.show_orbits = !self.show_orbits,
+            Some(KeyCode::F7) => self.show_bounds = !self.show_bounds,
+            Some(KeyCode::F8) => {
+                self.boundary_mode = match self.boundary_mode {
+                    BoundaryMode::Open => BoundaryMode::Wrap,
+                    BoundaryMode::Wrap => BoundaryMode::Bounce,
+                    BoundaryMode::Bounce => BoundaryMode::Open,
+                };
+            }
+            // Doubles the fast-forward up to the cap, then drops back to real time
+            Some(KeyCode::F6) => {
+                self.frame_skip = if self.frame_skip >= MAX_FRAME_SKIP { 1 } else { self.frame_skip * 2 };
+            }
+            Some(KeyCode::Key1) => {
+                self.force_mode = match self.force_mode {
+                    ForceMode::BruteForce => ForceMode::BarnesHut,
+                    ForceMode::BarnesHut => ForceMode::BruteForce,
+                };
+            }
+            Some(KeyCode::Key2) => self.load_binary_preset(),
+            Some(KeyCode::Key5) => self.rest_frame = !self.rest_frame && self.selected.is_some(),
+            Some(KeyCode::Key6) => self.show_star_force = !self.show_star_force,
+            Some(KeyCode::Key7) => self.recycle_expired = !self.recycle_expired,
+            Some(KeyCode::Key8) => self.show_drift = !self.show_drift,
+            Some(KeyCode::Key0) => self.show_lensing = !self.show_lensing,
+            Some(KeyCode::Key9) => {
+                self.show_density_profile = !self.show_density_profile;
+                self.density_profile.clear();
+            }
+            Some(KeyCode::LBracket | KeyCode::RBracket) if !self.north_up => {
+                let step = if input.keycode == Some(KeyCode::LBracket) { -VIEW_ROTATION_STEP } else { VIEW_ROTATION_STEP };
+                self.view_angle = (self.view_angle + step).rem_euclid(2.0 * PI);
+            }
+            // Shift+' locks north up; ' alone shows the compass
+            Some(KeyCode::Apostrophe) if input.mods.contains(KeyMods::SHIFT) => {
+                self.north_up = !self.north_up;
+                if self.north_up {
+                    self.view_angle = 0.0;
+                }
+            }
+            Some(KeyCode::Apostrophe) => self.show_compass = !self.show_compass,
+            Some(KeyCode::Grave) if input.mods.contains(KeyMods::SHIFT) => self.undo_clear_visuals(),
+            Some(KeyCode::Grave) => self.clear_visuals(),
+            Some(KeyCode::Semicolon) => self.motion_blur = !self.motion_blur,
+            Some(KeyCode::Slash) => self.show_hill_spheres = !self.show_hill_spheres,
+            Some(KeyCode::Comma) => self.double_precision = !self.double_precision,
+            Some(KeyCode::Period) => {
+                self.freeze_seed = !self.freeze_seed;
+                if self.freeze_seed {
+                    println!("Seed {} frozen; start with --seed {} to reproduce this disk", self.seed, self.seed);
+                }
+            }
+            Some(KeyCode::Backslash) => self.size_softening = !self.size_softening,
+            Some(KeyCode::End) => self.smooth_zoom = !self.smooth_zoom,
+            Some(KeyCode::Insert) => {
+                self.show_cursor_escape = !self.show_cursor_escape;
+                self.cursor_escape = None;
+            }
+            Some(KeyCode::PageUp) | Some(KeyCode::PageDown) => {
+                self.render_quality = if input.keycode == Some(KeyCode::PageUp) {
+                    self.render_quality.finer()
+                } else {
+                    self.render_quality.coarser()
+                };
+                self.particle_mesh = None;
+            }
+            Some(KeyCode::F12) => {
+                self.track_leader = !self.track_leader;
+                self.leader = None;
+                self.leader_id = None;
+                self.leader_path.clear();
+            }
+            Some(KeyCode::F11) => self.panel_blocks_clicks = !self.panel_blocks_clicks,
+            Some(KeyCode::F10) => {
+                self.smooth_motion = !self.smooth_motion;
+                self.physics_clock = 0.0;
+                for particle in &mut self.particles {
+                    particle.prev_position = particle.position;
+                }
+            }
+            Some(KeyCode::Tab) => {
+                self.show_mass_list = !self.show_mass_list;
+                self.mass_ranking = rank_by_mass(&self.particles);
+                self.mass_ranking_age = 0;
+            }
+            Some(KeyCode::Key3) => self.adding_tracers = !self.adding_tracers,
+            Some(KeyCode::Y) => {
+                self.star_home = self.screen_to_world(ctx.mouse.position());
+                println!("Star will start at ({:.0}, {:.0}) on the next reset", self.star_home.x, self.star_home.y);
+            }
+            Some(KeyCode::F9) => self.toggle_recording(ctx),
+            Some(KeyCode::Equals) => self.ui_scale = (self.ui_scale + UI_SCALE_STEP).min(3.0),
+            Some(KeyCode::Minus) => self.ui_scale = (self.ui_scale - UI_SCALE_STEP).max(0.5),
+            // Shift+O saves the current particles where O loads them from
+            Some(KeyCode::O) if input.mods.contains(KeyMods::SHIFT) => {
+                match std::fs::write(&self.csv_path, particles_to_csv(&self.particles)) {
+                    Ok(()) => println!("Saved {} particles to {}", self.particles.len(), self.csv_path),
+                    Err(err) => eprintln!("Failed to save particles to {}: {}", self.csv_path, err),
+                }
+            }
+            Some(KeyCode::O) => {
+                let path = self.csv_path.clone();
+                if let Err(err) = self.load_particles_csv(&path) {
+                    eprintln!("Failed to load particles: {}", err);
+                }
+            }
+            Some(KeyCode::Q) => {
+                self.quadtree_overlay = match self.quadtree_overlay {
+                    QuadtreeOverlay::Off => QuadtreeOverlay::Cells,
+                    QuadtreeOverlay::Cells => QuadtreeOverlay::CellsAndMass,
+                    QuadtreeOverlay::CellsAndMass => QuadtreeOverlay::Off,
+                };
+            }
+            Some(KeyCode::C) => print!("{}", particles_to_rust(&self.particles)),
+            Some(KeyCode::Escape) => self.selected = None,
+            // Tracking a particle starts its trail; untracking drops it
+            Some(KeyCode::Key4) => {
+                if let Some(index) = self.selected {
+                    let particle = &mut self.particles[index];
+                    particle.trail = match particle.trail {
+                        Some(_) => None,
+                        None => Some(VecDeque::with_capacity(TRAIL_LENGTH)),
+                    };
+                }
+            }
+            Some(KeyCode::F) => {
+                if let Some(index) = self.selected {
+                    let particle = &mut self.particles[index];
+                    particle.fixed = !particle.fixed;
+                    particle.velocity = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+                }
+            }
+            Some(KeyCode::Delete) => {
+                if let Some(index) = self.selected {
+                    self.remove_particle(index);
+                }
+            }
+            Some(KeyCode::L) => {
+                self.live_particle_count = !self.live_particle_count;
+                if self.live_particle_count {
+                    self.apply_particle_count();
+                }
+            }
+            Some(KeyCode::Home) => self.fit_view(input.mods.contains(KeyMods::SHIFT)),
+            Some(KeyCode::W) => self.pan.y += 10.0 / self.zoom,
+            Some(KeyCode::S) => self.pan.y -= 10.0 / self.zoom,
+            Some(KeyCode::A) => self.pan.x += 10.0 / self.zoom,
+            Some(KeyCode::D) => self.pan.x -= 10.0 / self.zoom,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
+        let index = self.focused_input;
+        let slider = &mut self.sliders[index];
+        if let Some(text_input) = &mut slider.text_input {
+            if character.is_numeric() || character == '\x08' {
+                if character == '\x08' {
+                    text_input.pop();
+                } else {
+                    text_input.push(character);
+                }
+                if let Ok(value) = text_input.parse::<f32>() {
+                    if value >= slider.min && value <= slider.max {
+                        slider.value = value;
+                        self.apply_slider(index);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs `steps` physics steps with no window or graphics context and returns
+// a one-line summary, for machines without a display. Stops early at the
+// Stop At Step target, like a windowed run pausing there.
+fn run_headless(state: &mut SimulationState, steps: u64) -> String {
+    let start = Instant::now();
+    let first = state.steps;
+    while state.steps < first + steps {
+        if state.tick() {
+            break;
+        }
+    }
+    let steps = state.steps - first;
+    let elapsed = start.elapsed().as_secs_f32();
+    let max_mass = state.particles.iter().map(|p| p.mass).fold(0.0, f32::max);
+    format!(
+        "{} steps in {:.2} s ({:.0} steps/s): {} particles left, heaviest {:.1}",
+        steps,
+        elapsed,
+        steps as f32 / elapsed.max(f32::EPSILON),
+        state.particles.len(),
+        max_mass
+    )
+}
+
+// Splits a --sweep spec like "Softening=0.5,1,2" into the slider label and values
+fn parse_sweep(spec: &str) -> Result<(String, Vec<f32>), String> {
+    let (label, values) = spec.split_once('=').ok_or_else(|| format!("expected <parameter>=<values>, got {:?}", spec))?;
+    let values = values
+        .split(',')
+        .map(|value| value.trim().parse::<f32>().map_err(|_| format!("{:?} is not a number", value.trim())))
+        .collect::<Result<Vec<f32>, String>>()?;
+    Ok((label.trim().to_string(), values))
+}
+
+// Resets and runs `steps` headless steps once per value of the slider called
+// `label`, returning one SWEEP_HEADER row per run. Every run uses the same
+// seed (the frozen one, or one rolled here) so only the parameter differs.
+fn run_sweep(state: &mut SimulationState, label: &str, values: &[f32], steps: u64) -> Result<Vec<String>, String> {
+    let index = state
+        .sliders
+        .iter()
+        .position(|slider| slider.label.eq_ignore_ascii_case(label))
+        .ok_or_else(|| format!("no parameter called {:?}", label))?;
+    let frozen = state.freeze_seed;
+    if !frozen {
+        state.seed = rand::random();
+    }
+    state.freeze_seed = true;
+    let mut rows = Vec::new();
+    for &value in values {
+        let slider = &mut state.sliders[index];
+        slider.value = slider.snap(value.clamp(slider.min, slider.max));
+        let value = slider.value;
+        state.apply_slider(index);
+        state.reset();
+        run_headless(state, steps);
+        let (energy_drift, _, _) = state.conserved_at_reset.drift(&Conserved::measure(&state.particles));
+        let max_mass = state.particles.iter().map(|p| p.mass).fold(0.0, f32::max);
+        rows.push(format!(
+            "{},{},{},{},{},{},{},{}",
+            state.sliders[index].label,
+            value,
+            state.seed,
+            state.steps,
+            energy_drift,
+            state.merges,
+            max_mass,
+            state.particles.len()
+        ));
+    }
+    state.freeze_seed = frozen;
+    Ok(rows)
+}
+
+fn main() -> GameResult {
+    let mut state = SimulationState::new();
+
+    let args: Vec<String> = std::env::args().collect();
+    let flag_value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+    if let Some(au) = flag_value("--au-per-unit").and_then(|value| value.parse().ok()) {
+        state.real_units.distance_scale = au;
+    }
+    if let Some(years) = flag_value("--years-per-time").and_then(|value| value.parse().ok()) {
+        state.real_units.time_scale = years;
+    }
+    // --seed <n> freezes the reset seed, rebuilding the same disk every time
+    if let Some(seed) = flag_value("--seed") {
+        match seed.parse() {
+            Ok(seed) => {
+                state.seed = seed;
+                state.freeze_seed = true;
+                state.reset();
+            }
+            Err(_) => eprintln!("Ignoring --seed {}: expected a whole number", seed),
+        }
+    }
+    if let Some(limit) = flag_value("--safe-particles").and_then(|value| value.parse().ok()) {
+        state.safe_particles = limit;
+    }
+    match flag_value("--zoom-step").map(|value| value.parse::<f32>()) {
+        Some(Ok(step)) if step > 1.0 => state.zoom_step = step,
+        Some(_) => eprintln!("Ignoring --zoom-step: expected a factor above 1, e.g. 1.05"),
+        None => (),
+    }
+    // --bounds <width>x<height>, centered on the window
+    if let Some((w, h)) = flag_value("--bounds").and_then(|value| value.split_once('x')) {
+        match (w.parse::<f32>(), h.parse::<f32>()) {
+            (Ok(w), Ok(h)) if w > 0.0 && h > 0.0 => {
+                state.world_bounds = graphics::Rect::new((WINDOW_WIDTH - w) / 2.0, (WINDOW_HEIGHT - h) / 2.0, w, h);
+            }
+            _ => eprintln!("Ignoring --bounds {}x{}: expected positive <width>x<height>", w, h),
+        }
+    }
+    if let Some(dir) = args.iter().position(|arg| arg == "--frames-dir").and_then(|i| args.get(i + 1)) {
+        state.frames_dir = format!("/{}", dir.trim_start_matches('/'));
+    }
+    if let Some(path) = args.iter().position(|arg| arg == "--load").and_then(|i| args.get(i + 1)) {
+        state.csv_path = path.clone();
+        if let Err(err) = state.load_particles_csv(path) {
+            eprintln!("Failed to load particles: {}", err);
+        }
+    }
+
+    if let Some(path) = flag_value("--script") {
+        let loaded = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| parse_script(&contents).map_err(|err| err.to_string()));
+        match loaded {
+            Ok(events) => state.script = events,
+            Err(err) => eprintln!("Failed to load script {}: {}", path, err),
+        }
+    }
+
+    // --sweep <parameter>=<v1>,<v2>,... runs each value headless and writes a CSV
+    if let Some(spec) = flag_value("--sweep") {
+        let steps = flag_value("--headless").and_then(|value| value.parse().ok()).unwrap_or(SWEEP_DEFAULT_STEPS);
+        let rows = parse_sweep(spec).and_then(|(label, values)| run_sweep(&mut state, &label, &values, steps));
+        match rows {
+            Ok(rows) => {
+                let csv = format!("{}\n{}\n", SWEEP_HEADER, rows.join("\n"));
+                match std::fs::write(DEFAULT_SWEEP_CSV, csv) {
+                    Ok(()) => println!("Wrote {} runs to {} (seed {})", rows.len(), DEFAULT_SWEEP_CSV, state.seed),
+                    Err(err) => eprintln!("Failed to write {}: {}", DEFAULT_SWEEP_CSV, err),
+                }
+            }
+            Err(err) => eprintln!("Sweep failed: {}", err),
+        }
+        return Ok(());
+    }
+
+    // --headless <steps> never opens a window
+    if let Some(steps) = flag_value("--headless") {
+        let Ok(steps) = steps.parse::<u64>() else {
+            eprintln!("Ignoring --headless {}: expected a step count", steps);
+            return Ok(());
+        };
+        println!("{}", run_headless(&mut state, steps));
+        return Ok(());
+    }
+
+    let cb = ggez::ContextBuilder::new("solar_system", "user")
+        .window_setup(
+            ggez::conf::WindowSetup::default()
+                .title("Solar System Formation Simulator")
+                .samples(ggez::conf::NumSamples::Four),
+        )
+        .window_mode(ggez::conf::WindowMode::default().dimensions(WINDOW_WIDTH, WINDOW_HEIGHT));
+    let (ctx, event_loop) = match cb.build() {
+        Ok(built) => built,
+        Err(err) => {
+            eprintln!("Couldn't open a window ({}).", err);
+            eprintln!("Without a display, run the physics alone with --headless <steps>.");
+            return Err(err);
+        }
+    };
     event::run(ctx, event_loop, state)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ForceSettings {
+        ForceSettings {
+            is_3d: false,
+            gravity: true,
+            exponent: 2.0,
+            drag: 0.0,
+            max_acceleration: 0.0,
+            cutoff: 0.0,
+            softening: 0.0,
+            size_softening: false,
+        }
+    }
+
+    fn acceleration_of_first(particles: &[Particle], settings: &ForceSettings) -> Vector3<f32> {
+        let mut body = particles[0].clone();
+        body.calculate_acceleration(Some(0), particles, None, settings);
+        body.acceleration
+    }
+
+    fn state_with(particles: Vec<Particle>) -> SimulationState {
+        let mut state = SimulationState::new();
+        state.replace_particles(particles, 1);
+        state
+    }
+
+    #[test]
+    fn two_bodies_pull_each_other_not_themselves() {
+        let particles = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(100.0, 0.0, 0.0, 50.0)];
+        let acceleration = acceleration_of_first(&particles, &settings());
+        let expected = G * 50.0 / (100.0 * 100.0);
+        assert!((acceleration.x - expected).abs() < 1e-6, "{} vs {}", acceleration.x, expected);
+        assert!(acceleration.y.abs() < 1e-9 && acceleration.z.abs() < 1e-9);
+    }
+
+    #[test]
+    fn barnes_hut_skips_the_particles_own_leaf() {
+        let particles = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(100.0, 0.0, 0.0, 50.0)];
+        let tree = QuadNode::build(&particles);
+        let mut body = particles[0].clone();
+        body.calculate_acceleration(Some(0), &particles, Some(&tree), &settings());
+        assert!((body.acceleration.x - G * 50.0 / 10000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn more_softening_weakens_a_close_pass() {
+        let particles = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(8.0, 0.0, 0.0, 50.0)];
+        let mut soft = settings();
+        let sharp = acceleration_of_first(&particles, &soft).x;
+        soft.softening = 3.0;
+        let softened = acceleration_of_first(&particles, &soft).x;
+        assert!(softened > 0.0 && softened < sharp, "{} should be below {}", softened, sharp);
+        // ε² is added to d², so the pull is G m d / (d² + ε²)^1.5
+        let expected = G * 50.0 * 8.0 / (64.0f32 + 9.0).powf(1.5);
+        assert!((softened - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn raising_the_count_live_adds_the_difference() {
+        let mut state = SimulationState::new();
+        let before = state.particles.len();
+        state.particle_count = before - 1 + 25;
+        state.apply_particle_count();
+        assert_eq!(state.particles.len(), before + 25);
+        state.particle_count -= 10;
+        state.apply_particle_count();
+        assert_eq!(state.particles.len(), before + 15);
+
+        // Moving the Central Stars slider doesn't change the stars in play until a reset
+        state.sliders[20].value = 3.0;
+        state.particle_count += 5;
+        state.apply_particle_count();
+        assert_eq!(state.particles.len(), before + 20);
+
+        state.sliders[20].value = 2.0;
+        state.reset();
+        let disk = state.particles.len() - 2;
+        state.sliders[20].value = 1.0;
+        state.particle_count = disk + 10;
+        state.apply_particle_count();
+        assert_eq!(state.particles.len(), disk + 2 + 10);
+    }
+
+    #[test]
+    fn dominant_attractor_is_the_strongest_pull_not_the_nearest() {
+        // A light neighbour at 10 pulls with 1/100; the star at 100 with 1000/10000
+        let particles = vec![
+            Particle::new(0.0, 0.0, 0.0, 1.0),
+            Particle::new(10.0, 0.0, 0.0, 1.0),
+            Particle::new(-100.0, 0.0, 0.0, 1000.0),
+        ];
+        let (index, force) = dominant_attractor(&particles, 0).unwrap();
+        assert_eq!(index, 2);
+        assert!((force - G * 1000.0 / 10000.0).abs() < 1e-6);
+        assert!(dominant_attractor(&particles[..1], 0).is_none());
+    }
+
+    #[test]
+    fn exported_rust_reproduces_positions() {
+        let mut particles = vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(912.25, 580.5, -3.75, 2.5)];
+        particles[1].velocity = Vector3 { x: 0.125, y: -2.5, z: 0.0 };
+        let code = particles_to_rust(&particles);
+        let parsed: Vec<[f32; 4]> = code
+            .lines()
+            .filter_map(|line| line.strip_prefix("let mut particle = Particle::new(")?.strip_suffix(");"))
+            .map(|args| {
+                let values: Vec<f32> = args.split(',').map(|value| value.trim().parse().unwrap()).collect();
+                [values[0], values[1], values[2], values[3]]
+            })
+            .collect();
+        assert_eq!(parsed.len(), particles.len());
+        for (values, particle) in parsed.iter().zip(&particles) {
+            assert!((values[0] - particle.position.x).abs() < 0.01);
+            assert!((values[1] - particle.position.y).abs() < 0.01);
+            assert!((values[2] - particle.position.z).abs() < 0.01);
+            assert!((values[3] - particle.mass).abs() < 0.001);
+        }
+        assert!(code.contains("x: 0.1250, y: -2.5000"));
+    }
+
+    #[test]
+    fn falling_into_the_star_leaves_one_fading_ripple() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(801.0, 600.0, 0.0, 1.0)]);
+        state.tick();
+        assert_eq!(state.particles.len(), 1);
+        assert_eq!(state.merge_effects.len(), 1);
+        for _ in 0..MERGE_EFFECT_LIFETIME - 1 {
+            state.animate(1.0 / 60.0);
+        }
+        assert_eq!(state.merge_effects.len(), 1);
+        state.animate(1.0 / 60.0);
+        assert!(state.merge_effects.is_empty());
+    }
+
+    #[test]
+    fn parallax_is_a_fixed_fraction_of_the_pan() {
+        for (x, y) in [(0.0, 0.0), (100.0, -50.0), (-7.5, 2000.0)] {
+            let offset = parallax_offset(Point2 { x, y });
+            assert_eq!((offset.x, offset.y), (x * STARFIELD_PARALLAX, y * STARFIELD_PARALLAX));
+        }
+    }
+
+    #[test]
+    fn nudges_move_the_selection_by_one_step() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.selected = Some(1);
+        state.nudge_selected(1.0, 0.0, false);
+        state.nudge_selected(0.0, -1.0, true);
+        let particle = &state.particles[1];
+        assert_eq!(particle.position.x, 900.0 + NUDGE_POSITION_STEP);
+        assert_eq!(particle.position.y, 600.0);
+        assert_eq!(particle.velocity.x, 0.0);
+        assert_eq!(particle.velocity.y, -NUDGE_VELOCITY_STEP);
+        assert_eq!(state.particles[0].position.x, 800.0);
+    }
+
+    #[test]
+    fn counters_track_steps_and_elapsed_time() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0)]);
+        let dt = 0.02;
+        for _ in 0..50 {
+            state.step(dt);
+        }
+        assert_eq!(state.steps, 50);
+        assert!((state.sim_time - 50.0 * dt).abs() < 1e-4);
+    }
+
+    #[test]
+    fn force_every_two_recomputes_on_alternate_steps() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity = Vector3 { x: 0.0, y: 3.0, z: 0.0 };
+        state.sliders[7].value = 2.0;
+        let mut accelerations = Vec::new();
+        for _ in 0..4 {
+            state.step(0.01);
+            let a = state.particles[1].acceleration;
+            accelerations.push((a.x, a.y));
+        }
+        // Steps 0 and 2 recompute; 1 and 3 reuse what the step before found
+        assert_eq!(accelerations[1], accelerations[0]);
+        assert_ne!(accelerations[2], accelerations[1]);
+        assert_eq!(accelerations[3], accelerations[2]);
+    }
+
+    #[test]
+    fn quadtree_leaves_for_simple_layouts() {
+        let at = |points: &[(f32, f32)]| points.iter().map(|&(x, y)| Particle::new(x, y, 0.0, 1.0)).collect::<Vec<_>>();
+        assert_eq!(QuadNode::build(&at(&[(5.0, 5.0)])).leaf_count(), 1);
+        let corners = at(&[(0.0, 0.0), (100.0, 0.0), (0.0, 100.0), (100.0, 100.0)]);
+        let tree = QuadNode::build(&corners);
+        assert_eq!(tree.leaf_count(), 4);
+        assert_eq!(tree.mass, 4.0);
+        // Two close bodies split three levels down: 3 + 3 + 3 + 4 leaves
+        assert_eq!(QuadNode::build(&at(&[(0.0, 0.0), (10.0, 10.0), (100.0, 100.0)])).leaf_count(), 13);
+    }
+
+    #[test]
+    fn tilt_squashes_vertically_and_shrinks_the_far_side() {
+        let center = tilt_projection(WINDOW_CENTER);
+        assert_eq!((center.x, center.y), (WINDOW_CENTER.x, WINDOW_CENTER.y));
+        // Points on the center row are left where they are
+        let side = tilt_projection(Point2 { x: WINDOW_CENTER.x + 100.0, y: WINDOW_CENTER.y });
+        assert_eq!((side.x, side.y), (WINDOW_CENTER.x + 100.0, WINDOW_CENTER.y));
+        let far = tilt_projection(Point2 { x: WINDOW_CENTER.x + 100.0, y: WINDOW_CENTER.y - 200.0 });
+        let near = tilt_projection(Point2 { x: WINDOW_CENTER.x + 100.0, y: WINDOW_CENTER.y + 200.0 });
+        let depth = 1.0 - TILT_PERSPECTIVE * 200.0 / WINDOW_HEIGHT;
+        assert!((far.x - (WINDOW_CENTER.x + 100.0 * depth)).abs() < 1e-3);
+        assert!((far.y - (WINDOW_CENTER.y - 200.0 * TILT_FORESHORTENING * depth)).abs() < 1e-3);
+        assert!(far.x - WINDOW_CENTER.x < near.x - WINDOW_CENTER.x);
+    }
+
+    #[test]
+    fn kicking_the_star_changes_only_its_velocity() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity = Vector3 { x: 0.0, y: 3.0, z: 0.0 };
+        state.sliders[8].value = 5.0;
+        state.sliders[9].value = 90.0;
+        let kick = state.kick_from_sliders();
+        assert!(kick.x.abs() < 1e-5 && (kick.y - 5.0).abs() < 1e-5);
+        state.kick_star(Vector3 { x: 1.5, y: -2.0, z: 0.0 });
+        let star = &state.particles[0];
+        assert_eq!((star.velocity.x, star.velocity.y, star.velocity.z), (1.5, -2.0, 0.0));
+        assert_eq!((star.position.x, star.position.y), (800.0, 600.0));
+        assert_eq!((state.particles[1].velocity.x, state.particles[1].velocity.y), (0.0, 3.0));
+    }
+
+    #[test]
+    fn bound_pairs_are_found_and_flybys_are_not() {
+        let mut particles = vec![
+            Particle::new(800.0, 600.0, 0.0, 1000.0),
+            Particle::new(1000.0, 600.0, 0.0, 10.0),
+            Particle::new(1010.0, 600.0, 0.0, 10.0),
+            Particle::new(600.0, 600.0, 0.0, 10.0),
+            Particle::new(610.0, 600.0, 0.0, 10.0),
+        ];
+        // Circular mutual orbit for the first pair: v_rel = sqrt(G (m1 + m2) / d) ≈ 1.41
+        particles[2].velocity = Vector3 { x: 0.0, y: 1.4, z: 0.0 };
+        // The second pair passes at ten times escape speed
+        particles[4].velocity = Vector3 { x: 0.0, y: 20.0, z: 0.0 };
+        assert!(is_bound_pair(&particles[1], &particles[2]));
+        assert!(!is_bound_pair(&particles[3], &particles[4]));
+        assert_eq!(find_bound_groups(&particles, 0), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn clicking_a_stepped_slider_snaps_to_the_nearest_step() {
+        let mut slider = Slider::new(1.0, 1.0, 10.0, "Force Every", 330.0, false).with_step(1.0);
+        // 3.4 of the way along the 1-10 track
+        slider.set_from_x(150.0 + 200.0 * (3.4 - 1.0) / 9.0, 1.0);
+        assert_eq!(slider.value, 3.0);
+        slider.set_from_x(150.0 + 200.0 * (3.6 - 1.0) / 9.0, 1.0);
+        assert_eq!(slider.value, 4.0);
+        // Clamped past the end, and the scale divides the click back down
+        slider.set_from_x(1000.0, 2.0);
+        assert_eq!(slider.value, 10.0);
+        let mut halves = Slider::new(0.0, 0.0, 2.0, "Test", 0.0, false).with_step(0.5);
+        halves.set_from_x(150.0 + 200.0 * 0.3, 1.0);
+        assert_eq!(halves.value, 0.5);
+    }
+
+    #[test]
+    fn clearing_keeps_only_the_star() {
+        let mut state = SimulationState::new();
+        let star_mass = state.particles.iter().map(|p| p.mass).fold(0.0, f32::max);
+        state.selected = Some(3);
+        state.clear_to_star();
+        assert_eq!(state.particles.len(), 1);
+        assert_eq!(state.particles[0].mass, star_mass);
+        assert_eq!(state.selected, None);
+    }
+
+    #[test]
+    fn every_particle_is_one_instance_of_a_shared_mesh() {
+        let mut particles = vec![Particle::new(800.0, 600.0, 0.0, 1000.0)];
+        particles.extend((0..500).map(|i| Particle::new(500.0 + i as f32, 300.0, 0.0, 1.0)));
+        let state = state_with(particles);
+        let (instances, streaks) = state.particle_instances(None);
+        assert_eq!(instances.len(), state.particles.len());
+        assert!(streaks.is_empty());
+        let graphics::Transform::Values { dest, scale, .. } = instances[1].transform else {
+            panic!("expected a plain placement");
+        };
+        let expected = state.world_to_screen(&state.particles[1].position);
+        assert_eq!((dest.x, dest.y), (expected.x, expected.y));
+        assert_eq!(scale.x, state.particles[1].radius * state.zoom);
+    }
+
+    #[test]
+    fn clamping_keeps_direction_and_caps_speed() {
+        let mut fast = Vector3 { x: 30.0, y: 40.0, z: 0.0 };
+        clamp_magnitude(&mut fast, 10.0);
+        assert!((fast.x - 6.0).abs() < 1e-5 && (fast.y - 8.0).abs() < 1e-5);
+        let mut slow = Vector3 { x: 3.0, y: 4.0, z: 0.0 };
+        clamp_magnitude(&mut slow, 10.0);
+        assert_eq!((slow.x, slow.y), (3.0, 4.0));
+    }
+
+    #[test]
+    fn csv_loads_particles_and_reports_bad_rows() {
+        let particles = parse_particle_csv("x,y,vx,vy,mass\n800,600,0,0,1000\n\n1000,600,0,2.2,3\n", &Units::SIMULATION).unwrap();
+        assert_eq!(particles.len(), 2);
+        assert_eq!((particles[1].position.x, particles[1].position.y), (1000.0, 600.0));
+        assert_eq!((particles[1].velocity.x, particles[1].velocity.y), (0.0, 2.2));
+        assert_eq!(particles[1].mass, 3.0);
+
+        let err = parse_particle_csv("x,y,vx,vy,mass\n800,600,0,0,1000\n1000,six,0,2.2,3\n", &Units::SIMULATION).err().unwrap();
+        assert_eq!(err.line, 3);
+        assert!(err.message.contains("invalid y value"));
+        let err = parse_particle_csv("x,y,vx,vy,mass\n1,2,3\n", &Units::SIMULATION).err().unwrap();
+        assert_eq!((err.line, err.message.as_str()), (2, "expected 5 fields, found 3"));
+        assert_eq!(parse_particle_csv("a,b\n", &Units::SIMULATION).err().unwrap().line, 1);
+    }
+
+    #[test]
+    fn marching_squares_traces_simple_fields() {
+        let one_corner = marching_squares(&[1.0, 0.0, 0.0, 0.0], 2, 2, 0.5);
+        assert_eq!(one_corner.len(), 1);
+        let [a, b] = one_corner[0];
+        assert_eq!((a.x, a.y, b.x, b.y), (0.0, 0.5, 0.5, 0.0));
+        assert!(marching_squares(&[0.0; 4], 2, 2, 0.5).is_empty());
+        assert!(marching_squares(&[1.0; 4], 2, 2, 0.5).is_empty());
+        // A peak in the middle of a 3x3 grid is ringed by a diamond, one edge per cell
+        let peak = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0];
+        let ring = marching_squares(&peak, 3, 3, 0.5);
+        assert_eq!(ring.len(), 4);
+        for point in ring.iter().flatten() {
+            assert!(((point.x - 1.0).abs() + (point.y - 1.0).abs() - 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn only_particles_above_the_threshold_get_labels() {
+        let particles: Vec<Particle> = [5.0, 25.0, 20.0, 1000.0, 0.5].iter().map(|&m| Particle::new(0.0, 0.0, 0.0, m)).collect();
+        assert_eq!(mass_label_indices(&particles, 20.0), vec![1, 3]);
+        assert!(mass_label_indices(&particles, 5000.0).is_empty());
+    }
+
+    #[test]
+    fn an_empty_simulation_steps_and_prepares_a_frame() {
+        let mut state = state_with(Vec::new());
+        state.show_density_profile = true;
+        state.show_flow = true;
+        state.show_clusters = true;
+        state.track_leader = true;
+        for _ in 0..3 {
+            state.advance_frame();
+            state.animate(1.0 / 60.0);
+            state.refresh_density_profile();
+            state.refresh_flow_field();
+            state.scan_clusters();
+        }
+        assert!(state.particles.is_empty());
+        assert_eq!(state.steps, 3);
+        assert!(state.particle_instances(None).0.is_empty());
+        assert!(state.star_index().is_none());
+    }
+
+    #[test]
+    fn accretion_history_records_the_star_growing() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0)]);
+        state.step(0.01);
+        state.particles.push(Particle::new(801.0, 600.0, 0.0, 4.0));
+        state.step(0.01);
+        let masses: Vec<f32> = state.star_mass_history.samples.iter().map(|&(_, mass)| mass).collect();
+        assert_eq!(masses, vec![1000.0, 1004.0]);
+        assert_eq!(state.star_mass_history.samples[1].0, 2);
+    }
+
+    #[test]
+    fn force_exponent_two_is_newtonian_and_three_falls_faster() {
+        for dist in [10.0f32, 50.0, 200.0] {
+            let newtonian = force_magnitude(100.0, dist * dist, 2.0);
+            assert!((newtonian - G * 100.0 / (dist * dist)).abs() < 1e-6);
+            let steeper = force_magnitude(100.0, dist * dist, 3.0);
+            assert!((steeper - G * 100.0 / (dist * dist * dist)).abs() < 1e-6);
+        }
+        // Doubling distance quarters the pull at 2 and eighths it at 3
+        let ratio = |exponent| force_magnitude(1.0, 400.0, exponent) / force_magnitude(1.0, 100.0, exponent);
+        assert!((ratio(2.0) - 0.25).abs() < 1e-6);
+        assert!((ratio(3.0) - 0.125).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tidal_stretch_across_a_body() {
+        // Near side at 90, far side at 110
+        let expected = G * 1000.0 * (1.0 / 8100.0 - 1.0 / 12100.0);
+        assert!((tidal_acceleration(1000.0, 100.0, 10.0).unwrap() - expected).abs() < 1e-6);
+        // Far out it falls off roughly as 4 G M r / d³
+        let far = tidal_acceleration(1000.0, 1000.0, 1.0).unwrap();
+        assert!((far - 4.0 * G * 1000.0 / 1.0e9).abs() / far < 1e-2);
+        assert!(tidal_acceleration(1000.0, 5.0, 5.0).is_none());
+    }
+
+    #[test]
+    fn recording_n_frames_writes_n_numbered_files() {
+        let dir = std::env::temp_dir().join(format!("solar_sim_frames_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut state = SimulationState::new();
+        state.frames_dir = dir.to_string_lossy().into_owned();
+        state.recording = true;
+        for _ in 0..5 {
+            state.save_frame(|path| std::fs::write(path, b"png").map_err(|err| err.to_string()));
+        }
+        let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(state.recorded_frames, 5);
+        assert_eq!(names, (0..5).map(|i| format!("frame_{:06}.png", i)).collect::<Vec<_>>());
+
+        state.save_frame(|_| Err("disk full".to_string()));
+        assert!(!state.recording);
+        assert_eq!(state.recorded_frames, 5);
+    }
+
+    #[test]
+    fn button_hits_follow_the_ui_scale() {
+        let button = Button::new(100.0, 10.0, 80.0, 30.0, "Reset");
+        let point = |x, y| Point2 { x, y };
+        assert!(button.contains(point(150.0, 20.0), 1.0));
+        assert!(!button.contains(point(250.0, 50.0), 1.0));
+        // At 2x the button covers 200..360 by 20..80
+        assert!(button.contains(point(250.0, 50.0), 2.0));
+        assert!(!button.contains(point(150.0, 20.0), 2.0));
+        assert!(button.contains(point(55.0, 6.0), 0.5));
+    }
+
+    #[test]
+    fn without_gravity_particles_drift_in_straight_lines() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.gravity_enabled = false;
+        state.particles[1].velocity = Vector3 { x: 2.0, y: -1.0, z: 0.0 };
+        for step in 1..=4 {
+            state.step(0.5);
+            let particle = &state.particles[1];
+            assert_eq!((particle.velocity.x, particle.velocity.y), (2.0, -1.0));
+            assert_eq!((particle.position.x, particle.position.y), (900.0 + step as f32, 600.0 - 0.5 * step as f32));
+        }
+        assert_eq!((state.particles[0].position.x, state.particles[0].position.y), (800.0, 600.0));
+    }
+
+    #[test]
+    fn new_particles_fade_in_to_full_opacity() {
+        let alphas: Vec<f32> = (0..=FADE_IN_STEPS + 10).map(fade_in_alpha).collect();
+        assert!(alphas[0] > 0.0 && alphas[0] < 1.0);
+        assert!(alphas.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(alphas[1] > alphas[0]);
+        assert_eq!(alphas[FADE_IN_STEPS as usize], 1.0);
+        assert_eq!(*alphas.last().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn time_steps_snap_to_the_nearest_power_of_two() {
+        assert_eq!(snap_to_power_of_two(0.016), 1.0 / 64.0);
+        assert_eq!(snap_to_power_of_two(0.03), 1.0 / 32.0);
+        assert_eq!(snap_to_power_of_two(0.125), 0.125);
+        assert_eq!(snap_to_power_of_two(0.001), 1.0 / 1024.0);
+    }
+
+    #[test]
+    fn grid_and_brute_force_find_the_same_collisions() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let particles: Vec<Particle> = (0..400)
+            .map(|_| Particle::new(rng.gen_range(0.0..600.0), rng.gen_range(0.0..600.0), 0.0, rng.gen_range(0.5..30.0)))
+            .collect();
+        for (cross_section, focusing) in [(1.0, 0.0), (2.5, 0.0), (1.0, 1.0)] {
+            let mut brute = colliding_pairs_brute(&particles, cross_section, focusing);
+            let mut grid = colliding_pairs_grid(&particles, cross_section, focusing);
+            brute.sort_unstable();
+            grid.sort_unstable();
+            assert!(!brute.is_empty());
+            assert_eq!(grid, brute);
+        }
+    }
+
+    #[test]
+    fn orbit_builder_gives_a_circular_relative_velocity() {
+        let mut state = state_with(Vec::new());
+        state.orbit_builder = OrbitBuilder::PickStar;
+        state.orbit_builder_click(Point2 { x: 800.0, y: 600.0 });
+        state.orbit_builder_click(Point2 { x: 800.0, y: 500.0 });
+        assert_eq!(state.particles.len(), 2);
+        let (star, planet) = (&state.particles[0], &state.particles[1]);
+        let (dx, dy) = (planet.position.x - star.position.x, planet.position.y - star.position.y);
+        let separation = (dx * dx + dy * dy).sqrt();
+        let (dvx, dvy) = (planet.velocity.x - star.velocity.x, planet.velocity.y - star.velocity.y);
+        let expected = (G * (star.mass + planet.mass) / separation).sqrt();
+        assert!(((dvx * dvx + dvy * dvy).sqrt() - expected).abs() < 1e-4);
+        // Perpendicular to the separation, and the pair's momentum stays zero
+        assert!((dx * dvx + dy * dvy).abs() < 1e-3);
+        assert!((star.mass * star.velocity.x + planet.mass * planet.velocity.x).abs() < 1e-3);
+        assert!((star.mass * star.velocity.y + planet.mass * planet.velocity.y).abs() < 1e-3);
+        assert!(state.orbit_builder == OrbitBuilder::Off);
+    }
+
+    #[test]
+    fn unit_conversions_scale_readouts() {
+        let units = Units::astronomical(0.01, 0.5);
+        assert!((units.distance(250.0) - 2.5).abs() < 1e-6);
+        assert!((units.time(4.0) - 2.0).abs() < 1e-6);
+        assert!((units.speed(10.0) - 0.2).abs() < 1e-6);
+        assert_eq!(units.speed_name(), "AU/yr");
+        assert_eq!(Units::SIMULATION.distance(250.0), 250.0);
+    }
+
+    #[test]
+    fn reset_places_no_overlapping_particles() {
+        let mut state = SimulationState::new();
+        state.particle_count = 400;
+        state.reset();
+        let particles = &state.particles;
+        assert!(particles.len() > 300);
+        for i in 0..particles.len() {
+            for j in (i + 1)..particles.len() {
+                let (a, b) = (&particles[i].position, &particles[j].position);
+                let distance = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt();
+                assert!(distance >= particles[i].radius + particles[j].radius, "{} and {} overlap", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_radius_mode_ignores_mass() {
+        let mut state = SimulationState::new();
+        state.toggle_radius_model();
+        state.add_large_mass(100.0, 100.0);
+        assert!(state.particles.iter().all(|particle| particle.radius == FIXED_PARTICLE_RADIUS));
+        state.reset();
+        assert!(state.particles.iter().all(|particle| particle.radius == FIXED_PARTICLE_RADIUS));
+        state.toggle_radius_model();
+        let star = state.star_index().unwrap();
+        assert_eq!(state.particles[star].radius, RadiusModel::ConstantDensity.radius(state.particles[star].mass));
+    }
+
+    #[test]
+    fn restoring_the_pause_snapshot_reproduces_the_paused_state() {
+        let mut state = SimulationState::new();
+        for _ in 0..5 {
+            state.step(0.01);
+        }
+        state.paused = false;
+        state.toggle_pause();
+        assert!(state.paused);
+        let positions: Vec<(f32, f32)> = state.particles.iter().map(|p| (p.position.x, p.position.y)).collect();
+        let (steps, time) = (state.steps, state.sim_time);
+        state.toggle_pause();
+        for _ in 0..20 {
+            state.step(0.01);
+        }
+        state.restore_last_pause();
+        assert!(state.paused);
+        assert_eq!((state.steps, state.sim_time), (steps, time));
+        assert_eq!(state.particles.iter().map(|p| (p.position.x, p.position.y)).collect::<Vec<_>>(), positions);
+    }
+
+    #[test]
+    fn three_steps_log_three_stats_rows() {
+        let path = std::env::temp_dir().join(format!("solar_sim_stats_{}.csv", std::process::id()));
+        let mut state = SimulationState::new();
+        let mut log = BufWriter::new(File::create(&path).unwrap());
+        writeln!(log, "{}", STATS_HEADER).unwrap();
+        state.stats_log = Some(log);
+        for _ in 0..3 {
+            state.step(0.01);
+        }
+        state.stats_log.take().unwrap().flush().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], STATS_HEADER);
+        let columns = STATS_HEADER.split(',').count();
+        for (step, row) in lines[1..].iter().enumerate() {
+            let fields: Vec<&str> = row.split(',').collect();
+            assert_eq!(fields.len(), columns);
+            assert_eq!(fields[0], (step + 1).to_string());
+            assert!(fields.iter().all(|field| field.parse::<f32>().is_ok()));
+        }
+    }
+
+    #[test]
+    fn focus_ring_pulses_within_its_bounds() {
+        let mut peak = FOCUS_RING_MIN;
+        let mut trough = FOCUS_RING_MAX;
+        for i in 0..=200 {
+            let elapsed = FOCUS_RING_INTRO + FOCUS_RING_PERIOD * i as f32 / 200.0;
+            let gap = focus_ring_gap(elapsed);
+            assert!((FOCUS_RING_MIN - 1e-4..=FOCUS_RING_MAX + 1e-4).contains(&gap), "gap {} at {}", gap, elapsed);
+            peak = peak.max(gap);
+            trough = trough.min(gap);
+        }
+        // The pulse actually swings across the range rather than sitting at one end
+        assert!(peak - trough > 0.9 * (FOCUS_RING_MAX - FOCUS_RING_MIN));
+        assert!(focus_ring_gap(0.0) > FOCUS_RING_MAX);
+    }
+
+    #[test]
+    fn collapse_fires_only_below_the_survivor_threshold() {
+        assert!(!has_collapsed(COLLAPSE_MIN_SURVIVORS, 100));
+        assert!(has_collapsed(COLLAPSE_MIN_SURVIVORS - 1, 100));
+        assert!(has_collapsed(0, 100));
+        // A run that never had enough bodies can't collapse
+        assert!(!has_collapsed(0, COLLAPSE_MIN_SURVIVORS - 1));
+    }
+
+    #[test]
+    fn birth_color_follows_the_spawn_radius() {
+        let inner = birth_ring_color(SPAWN_MIN_RADIUS);
+        assert_eq!((inner.r, inner.g, inner.b), (1.0, 0.0, 0.0));
+        let outer = birth_ring_color(SPAWN_MAX_RADIUS);
+        assert!((outer.r - 0.8).abs() < 1e-5 && outer.g == 0.0 && outer.b == 1.0);
+        // Out-of-ring distances clamp to the ends
+        assert_eq!(birth_ring_color(0.0), inner);
+        assert_eq!(birth_ring_color(SPAWN_MAX_RADIUS * 3.0), outer);
+
+        let state = SimulationState::new();
+        let star = state.star_index().unwrap();
+        let center = state.particles[star].position;
+        for (i, p) in state.particles.iter().enumerate().filter(|&(i, _)| i != star) {
+            let distance = ((p.position.x - center.x).powi(2) + (p.position.y - center.y).powi(2)).sqrt();
+            let expected = birth_ring_color(distance);
+            let error = (p.birth_color.r - expected.r).abs() + (p.birth_color.g - expected.g).abs() + (p.birth_color.b - expected.b).abs();
+            assert!(error < 1e-3, "particle {}", i);
+        }
+    }
+
+    #[test]
+    fn drag_shrinks_a_circular_orbit() {
+        let radius_after = |drag: f32| {
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+            state.particles[0].fixed = true;
+            state.particles[1].velocity = Vector3 { x: 0.0, y: (G * 1000.0 / 100.0).sqrt(), z: 0.0 };
+            state.sliders[4].value = 0.0;
+            state.sliders[10].value = 0.0;
+            state.sliders[13].value = drag;
+            for _ in 0..2000 {
+                state.step(0.01);
+            }
+            let p = &state.particles[1];
+            ((p.position.x - 800.0).powi(2) + (p.position.y - 600.0).powi(2)).sqrt()
+        };
+        assert!((radius_after(0.0) - 100.0).abs() < 1.0);
+        assert!(radius_after(0.05) < 95.0);
+    }
+
+    #[test]
+    fn anchors_stay_put_but_still_pull() {
+        for double_precision in [false, true] {
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 500.0), Particle::new(900.0, 600.0, 0.0, 500.0)]);
+            state.double_precision = double_precision;
+            state.particles[0].fixed = true;
+            for _ in 0..1000 {
+                state.step(0.01);
+            }
+            let (anchor, other) = (&state.particles[0], &state.particles[1]);
+            assert_eq!((anchor.position.x, anchor.position.y), (800.0, 600.0));
+            assert_eq!((anchor.velocity.x, anchor.velocity.y), (0.0, 0.0));
+            assert!(other.position.x < 899.0 && other.velocity.x < 0.0, "f64 {}", double_precision);
+        }
+    }
+
+    #[test]
+    fn timings_seed_then_ease_toward_new_samples() {
+        let mut timing = PhaseTiming::default();
+        timing.record(Duration::from_millis(10));
+        assert!((timing.average_ms - 10.0).abs() < 1e-4);
+        timing.record(Duration::from_millis(20));
+        assert!((timing.average_ms - (10.0 + 10.0 * TIMING_SMOOTHING)).abs() < 1e-4);
+        for _ in 0..500 {
+            timing.record(Duration::from_millis(20));
+        }
+        assert!((timing.average_ms - 20.0).abs() < 1e-3);
+        assert_eq!(timing.samples, 502);
+    }
+
+    #[test]
+    fn reset_centers_the_disk_on_the_star_home() {
+        let mut state = SimulationState::new();
+        state.star_home = Point2 { x: 300.0, y: 200.0 };
+        state.reset();
+        let star = &state.particles[state.star_index().unwrap()];
+        assert_eq!((star.position.x, star.position.y), (300.0, 200.0));
+        assert!(state.particles.len() > 1);
+        for p in state.particles.iter().filter(|p| p.mass < star.mass) {
+            let distance = ((p.position.x - 300.0).powi(2) + (p.position.y - 200.0).powi(2)).sqrt();
+            assert!((SPAWN_MIN_RADIUS - 1e-2..=SPAWN_MAX_RADIUS + 1e-2).contains(&distance), "{}", distance);
+        }
+    }
+
+    #[test]
+    fn binary_stars_start_on_a_bound_circular_orbit() {
+        let center = Point2 { x: 400.0, y: 300.0 };
+        let (mass_a, mass_b, separation) = (300.0, 100.0, 40.0);
+        let [a, b] = binary_star_pair(center, mass_a, mass_b, separation);
+        assert!((a.position.x * mass_a + b.position.x * mass_b) / (mass_a + mass_b) - center.x < 1e-3);
+        assert!((b.position.x - a.position.x - separation).abs() < 1e-3);
+        // Zero net momentum, and the relative speed is the circular one
+        assert!((a.velocity.y * mass_a + b.velocity.y * mass_b).abs() < 1e-3);
+        let relative_speed = b.velocity.y - a.velocity.y;
+        assert!((relative_speed - (G * (mass_a + mass_b) / separation).sqrt()).abs() < 1e-3);
+        let reduced_mass = mass_a * mass_b / (mass_a + mass_b);
+        let energy = 0.5 * reduced_mass * relative_speed.powi(2) - G * mass_a * mass_b / separation;
+        assert!(energy < 0.0);
+    }
+
+    #[test]
+    fn circular_orbit_reports_the_kepler_period() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[0].fixed = true;
+        state.particles[1].velocity = Vector3 { x: 0.0, y: (G * 1000.0 / 100.0).sqrt(), z: 0.0 };
+        state.sliders[4].value = 0.0;
+        state.sliders[10].value = 0.0;
+        state.selected = Some(1);
+        let expected = 2.0 * PI * (100.0f32.powi(3) / (G * 1000.0)).sqrt();
+        while state.period_tracker.as_ref().and_then(|tracker| tracker.period).is_none() {
+            assert!(state.sim_time < 2.0 * expected, "no full turn measured");
+            state.step(0.02);
+        }
+        let period = state.period_tracker.as_ref().unwrap().period.unwrap();
+        assert!((period - expected).abs() < 0.01 * expected, "{} vs {}", period, expected);
+    }
+
+    #[test]
+    fn merge_records_a_collision_event() {
+        let mut state = state_with(vec![
+            Particle::new(800.0, 600.0, 0.0, 1000.0),
+            Particle::new(100.0, 100.0, 0.0, 1.0),
+            Particle::new(101.0, 100.0, 0.0, 2.0),
+        ]);
+        state.step(0.001);
+        assert_eq!(state.collision_events.len(), 1);
+        let event = state.collision_events[0];
+        assert!(event.kind == CollisionKind::Merge);
+        assert_eq!((event.a, event.b), (2, 1));
+        assert!((event.position.x - 100.0).abs() < 0.1 && (event.position.y - 100.0).abs() < 0.1);
+        assert_eq!(state.particles.len(), 2);
+        assert_eq!(state.merges, 1);
+    }
+
+    #[test]
+    fn orbital_elements_round_trip_a_state_vector() {
+        let (a, e, periapsis, mu) = (100.0f32, 0.5f32, 1.0f32, 1000.0f32);
+        let semi_latus = a * (1.0 - e * e);
+        let reference = OrbitalElements { semi_major_axis: a, eccentricity: e, periapsis_angle: periapsis };
+        for true_anomaly in [0.0f32, 2.0, -2.5] {
+            let position = reference.point_at(true_anomaly);
+            // Radial and transverse speed from the vis-viva relations
+            let scale = (mu / semi_latus).sqrt();
+            let (radial, transverse) = (scale * e * true_anomaly.sin(), scale * (1.0 + e * true_anomaly.cos()));
+            let angle = periapsis + true_anomaly;
+            let velocity = Point2 {
+                x: radial * angle.cos() - transverse * angle.sin(),
+                y: radial * angle.sin() + transverse * angle.cos(),
+            };
+            let elements = OrbitalElements::from_state(position, velocity, mu).unwrap();
+            assert!((elements.semi_major_axis - a).abs() < 1e-2, "a {}", elements.semi_major_axis);
+            assert!((elements.eccentricity - e).abs() < 1e-4, "e {}", elements.eccentricity);
+            assert!((elements.periapsis_angle - periapsis).abs() < 1e-4);
+            let back = elements.point_at(true_anomaly);
+            assert!((back.x - position.x).abs() < 1e-2 && (back.y - position.y).abs() < 1e-2);
+        }
+        // Faster than escape speed has no ellipse
+        let escaping = Point2 { x: 0.0, y: (2.0 * mu / 50.0).sqrt() + 0.1 };
+        assert!(OrbitalElements::from_state(Point2 { x: 50.0, y: 0.0 }, escaping, mu).is_none());
+    }
+
+    #[test]
+    fn tracers_feel_gravity_without_pulling_back() {
+        for (force_mode, double_precision) in [(ForceMode::BruteForce, false), (ForceMode::BarnesHut, false), (ForceMode::BruteForce, true)] {
+            let mut tracer = Particle::new(900.0, 600.0, 0.0, 0.0);
+            tracer.tracer = true;
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), tracer]);
+            state.force_mode = force_mode;
+            state.double_precision = double_precision;
+            for _ in 0..10 {
+                state.step(0.01);
+            }
+            let (star, tracer) = (&state.particles[0], &state.particles[1]);
+            assert_eq!((star.velocity.x, star.velocity.y), (0.0, 0.0));
+            assert_eq!((star.position.x, star.position.y), (800.0, 600.0));
+            assert!(tracer.velocity.x < 0.0, "f64 {}", double_precision);
+        }
+    }
+
+    #[test]
+    fn frame_skip_steps_that_many_times_per_frame() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.frame_skip = 4;
+        state.advance_frame();
+        state.advance_frame();
+        assert_eq!(state.steps, 8);
+        assert_eq!(state.rendered_frames, 2);
+    }
+
+    #[test]
+    fn stability_check_separates_safe_and_unsafe_settings() {
+        // Wide softening and a small step follow a skimming orbit easily
+        assert!(!likely_unstable(10.0, 0.001, 1000.0));
+        // No softening and a big step around a heavy star can't
+        assert!(likely_unstable(0.0, 0.1, 10000.0));
+        assert!(likely_unstable(0.5, 0.1, 10000.0));
+        assert!(!likely_unstable(0.0, 0.1, 0.0));
+    }
+
+    #[test]
+    fn slow_impacts_merge_and_fast_ones_bounce() {
+        let collide = |speed: f32| {
+            let mut a = Particle::new(100.0, 100.0, 0.0, 1.0);
+            let mut b = Particle::new(101.0, 100.0, 0.0, 1.0);
+            a.velocity.x = speed / 2.0;
+            b.velocity.x = -speed / 2.0;
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), a, b]);
+            state.sliders[10].value = 0.0;
+            state.sliders[14].value = 5.0;
+            state.step(0.001);
+            state
+        };
+        let slow = collide(2.0);
+        assert_eq!(slow.particles.len(), 2);
+        assert!(slow.collision_events[0].kind == CollisionKind::Merge);
+
+        let fast = collide(20.0);
+        assert_eq!(fast.particles.len(), 3);
+        assert!(fast.collision_events[0].kind == CollisionKind::Bounce);
+        // Equal masses swap velocities along the line of centers, so they now separate
+        assert!(fast.particles[1].velocity.x < 0.0 && fast.particles[2].velocity.x > 0.0);
+        assert_eq!(fast.merges, 0);
+    }
+
+    #[test]
+    fn enclosing_radius_reaches_the_farthest_particle() {
+        let particles = vec![
+            Particle::new(10.0, 0.0, 0.0, 1.0),
+            Particle::new(0.0, -3.0, 4.0, 1.0),
+            Particle::new(-2.0, 0.0, 0.0, 1.0),
+        ];
+        let origin = Point3 { x: 0.0, y: 0.0, z: 0.0 };
+        assert_eq!(enclosing_radius(&particles, origin), 10.0);
+        assert_eq!(enclosing_radius(&particles, Point3 { x: 10.0, y: 0.0, z: 0.0 }), 12.0);
+        assert_eq!(enclosing_radius(&[], origin), 0.0);
+    }
+
+    #[test]
+    fn only_tracked_particles_grow_trails() {
+        let mut state = state_with(vec![
+            Particle::new(800.0, 600.0, 0.0, 1000.0),
+            Particle::new(900.0, 600.0, 0.0, 1.0),
+            Particle::new(700.0, 600.0, 0.0, 1.0),
+        ]);
+        state.particles[1].trail = Some(VecDeque::with_capacity(TRAIL_LENGTH));
+        for _ in 0..TRAIL_LENGTH + 20 {
+            state.step(0.01);
+        }
+        assert_eq!(state.particles[1].trail.as_ref().map(|trail| trail.len()), Some(TRAIL_LENGTH));
+        assert!(state.particles[0].trail.is_none() && state.particles[2].trail.is_none());
+        let last = *state.particles[1].trail.as_ref().unwrap().back().unwrap();
+        assert_eq!((last.x, last.y), (state.particles[1].position.x, state.particles[1].position.y));
+    }
+
+    #[test]
+    fn max_acceleration_caps_a_close_pass() {
+        let capped = ForceSettings { max_acceleration: 0.1, ..settings() };
+        let close = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(8.0, 0.0, 0.0, 50.0)];
+        assert!(acceleration_of_first(&close, &settings()).x > 0.5);
+        let acceleration = acceleration_of_first(&close, &capped);
+        assert!((acceleration.x - 0.1).abs() < 1e-6 && acceleration.y == 0.0);
+
+        // Distant pulls are well under the cap and pass through unchanged
+        let far = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(100.0, 0.0, 0.0, 50.0)];
+        assert_eq!(acceleration_of_first(&far, &capped).x, acceleration_of_first(&far, &settings()).x);
+    }
+
+    #[test]
+    fn shake_matches_sigma_and_repeats_with_a_seed() {
+        let calm = |count: usize| (0..count).map(|i| Particle::new(i as f32, 0.0, 0.0, 1.0)).collect::<Vec<_>>();
+        let mut particles = calm(5000);
+        shake_velocities(&mut particles, 2.0, false, 7);
+        let kicks: Vec<f32> = particles.iter().flat_map(|p| [p.velocity.x, p.velocity.y]).collect();
+        let mean = kicks.iter().sum::<f32>() / kicks.len() as f32;
+        let deviation = (kicks.iter().map(|k| (k - mean).powi(2)).sum::<f32>() / kicks.len() as f32).sqrt();
+        assert!(mean.abs() < 0.1, "mean {}", mean);
+        assert!((deviation - 2.0).abs() < 0.1, "sigma {}", deviation);
+        assert!(particles.iter().all(|p| p.velocity.z == 0.0));
+
+        let mut again = calm(5000);
+        shake_velocities(&mut again, 2.0, false, 7);
+        assert!(again.iter().zip(&particles).all(|(a, b)| a.velocity.x == b.velocity.x && a.velocity.y == b.velocity.y));
+        let mut other = calm(5000);
+        shake_velocities(&mut other, 2.0, false, 8);
+        assert!(other.iter().zip(&particles).any(|(a, b)| a.velocity.x != b.velocity.x));
+    }
+
+    #[test]
+    fn mass_list_sorts_heaviest_first_and_clicks_select_the_row() {
+        let mut state = state_with(vec![
+            Particle::new(100.0, 100.0, 0.0, 3.0),
+            Particle::new(200.0, 100.0, 0.0, 1000.0),
+            Particle::new(300.0, 100.0, 0.0, 7.0),
+            Particle::new(400.0, 100.0, 0.0, 1.0),
+        ]);
+        state.show_mass_list = true;
+        state.refresh_mass_ranking();
+        assert_eq!(state.mass_ranking, vec![1, 2, 0, 3]);
+        let masses: Vec<f32> = state.mass_ranking.iter().map(|&i| state.particles[i].mass).collect();
+        assert!(masses.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let rect = state.mass_list_rect();
+        let row = |n: f32| Point2 { x: rect.x + 5.0, y: rect.y + (n + 0.5) * MASS_LIST_ROW_HEIGHT * state.ui_scale };
+        assert_eq!(state.mass_list_hit(row(0.0)), None);
+        assert_eq!(state.mass_list_hit(row(1.0)), Some(1));
+        assert_eq!(state.mass_list_hit(row(3.0)), Some(0));
+        assert_eq!(state.mass_list_hit(row(5.0)), None);
+    }
+
+    #[test]
+    fn boundaries_use_the_configured_world_bounds() {
+        let run = |mode: BoundaryMode| {
+            let mut particle = Particle::new(310.0, 150.0, 0.0, 1.0);
+            particle.velocity.x = 5.0;
+            let mut state = state_with(vec![particle]);
+            state.world_bounds = graphics::Rect::new(100.0, 100.0, 200.0, 200.0);
+            state.boundary_mode = mode;
+            state.step(0.01);
+            state.particles.remove(0)
+        };
+        let wrapped = run(BoundaryMode::Wrap);
+        assert!((wrapped.position.x - 110.05).abs() < 1e-3, "{}", wrapped.position.x);
+        let bounced = run(BoundaryMode::Bounce);
+        assert_eq!(bounced.position.x, 300.0);
+        assert!(bounced.velocity.x < 0.0);
+        let open = run(BoundaryMode::Open);
+        assert!(open.position.x > 310.0);
+    }
+
+    #[test]
+    fn animation_runs_while_physics_is_paused() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity.y = 3.0;
+        state.paused = true;
+        let zoom = state.zoom;
+        state.move_camera(zoom * 2.0, state.pan, false);
+        let start = state.anim_time;
+        for _ in 0..10 {
+            state.animate(0.05);
+        }
+        assert!((state.anim_time - start - 0.5).abs() < 1e-5);
+        assert!(state.zoom > zoom);
+        assert_eq!((state.particles[1].position.x, state.particles[1].position.y), (900.0, 600.0));
+        assert_eq!(state.steps, 0);
+    }
+
+    #[test]
+    fn retrograde_fraction_matches_the_slider() {
+        let mut state = SimulationState::new();
+        state.clear_to_star();
+        state.sliders[17].value = 0.3;
+        let (center, _) = state.spawn_center();
+        let mut rng = StdRng::seed_from_u64(11);
+        let samples = 4000;
+        let retrograde = (0..samples)
+            .map(|_| state.spawn_orbiting_particle(&mut rng))
+            .filter(|p| (p.position.x - center.x) * p.velocity.y - (p.position.y - center.y) * p.velocity.x < 0.0)
+            .count();
+        let fraction = retrograde as f32 / samples as f32;
+        assert!((fraction - 0.3).abs() < 0.03, "{}", fraction);
+    }
+
+    #[test]
+    fn camera_flight_eases_onto_its_target_in_time() {
+        let mut state = SimulationState::new();
+        let (from_zoom, from_pan) = (state.zoom, state.pan);
+        let (to_zoom, to_pan) = (from_zoom * 4.0, Point2 { x: from_pan.x + 50.0, y: from_pan.y - 30.0 });
+        state.move_camera(to_zoom, to_pan, false);
+        let frame = CAMERA_FLIGHT_DURATION / 20.0;
+        let mut zooms = Vec::new();
+        for _ in 0..19 {
+            state.animate(frame);
+            zooms.push(state.zoom);
+        }
+        // Strictly between the ends and moving the right way the whole time
+        assert!(state.camera_flight.is_some());
+        assert!(zooms.windows(2).all(|pair| pair[1] > pair[0]));
+        assert!(zooms[0] > from_zoom && zooms[18] < to_zoom);
+        state.animate(frame * 1.01);
+        assert!(state.camera_flight.is_none());
+        assert_eq!((state.zoom, state.pan.x, state.pan.y), (to_zoom, to_pan.x, to_pan.y));
+    }
+
+    #[test]
+    fn cutoff_drops_distant_pairs_only() {
+        let cut = ForceSettings { cutoff: 50.0, ..settings() };
+        let far = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(60.0, 0.0, 0.0, 50.0)];
+        assert_eq!(acceleration_of_first(&far, &cut).x, 0.0);
+        assert!(acceleration_of_first(&far, &settings()).x > 0.0);
+        let near = vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(40.0, 0.0, 0.0, 50.0)];
+        assert_eq!(acceleration_of_first(&near, &cut).x, acceleration_of_first(&near, &settings()).x);
+    }
+
+    #[test]
+    fn rest_frame_pins_the_observer_to_screen_center() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity.y = 3.0;
+        state.selected = Some(1);
+        state.rest_frame = true;
+        for _ in 0..50 {
+            state.step(0.05);
+            let observer = state.world_to_screen(&state.particles[1].position);
+            assert!((observer.x - WINDOW_CENTER.x).abs() < 1e-3 && (observer.y - WINDOW_CENTER.y).abs() < 1e-3);
+        }
+        // The star, meanwhile, appears to move
+        let star = state.world_to_screen(&state.particles[0].position);
+        assert!((star.y - WINDOW_CENTER.y).abs() > 1.0);
+    }
+
+    #[test]
+    fn particle_warning_depends_on_the_force_mode() {
+        let count = DEFAULT_SAFE_PARTICLES + 100;
+        assert!(count > safe_particle_limit(ForceMode::BruteForce, DEFAULT_SAFE_PARTICLES));
+        assert!(count <= safe_particle_limit(ForceMode::BarnesHut, DEFAULT_SAFE_PARTICLES));
+        assert!(DEFAULT_SAFE_PARTICLES <= safe_particle_limit(ForceMode::BruteForce, DEFAULT_SAFE_PARTICLES));
+    }
+
+    #[test]
+    fn bookmarks_restore_the_exact_view() {
+        let mut state = SimulationState::new();
+        state.pan = Point2 { x: -123.456, y: 78.9 };
+        state.zoom = 0.3125;
+        state.bookmarks[2] = Some((state.pan, state.zoom));
+        // Survives the trip through the bookmarks file
+        state.bookmarks = parse_bookmarks(&format_bookmarks(&state.bookmarks));
+        state.pan = Point2 { x: 0.0, y: 0.0 };
+        state.zoom = 2.0;
+        state.recall_bookmark(2, true);
+        assert_eq!((state.pan.x, state.pan.y, state.zoom), (-123.456, 78.9, 0.3125));
+        // An empty slot leaves the view alone
+        state.recall_bookmark(0, true);
+        assert_eq!(state.zoom, 0.3125);
+        assert!(parse_bookmarks("0,1,2,3\n10,1,2,3\n1,x,2,3\n2,1,2,-1").iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn star_force_arrow_points_along_the_acceleration() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(860.0, 520.0, 0.0, 10.0)]);
+        state.step(0.001);
+        let star = &state.particles[0];
+        let acceleration = star.acceleration;
+        let magnitude = (acceleration.x * acceleration.x + acceleration.y * acceleration.y).sqrt();
+        let from = state.world_to_screen(&star.position);
+        let toward = state.world_to_screen(&Point3 {
+            x: star.position.x + acceleration.x / magnitude,
+            y: star.position.y + acceleration.y / magnitude,
+            z: star.position.z,
+        });
+        let tip = arrow_tip(from, toward, 30.0);
+        let (dx, dy) = (tip.x - from.x, tip.y - from.y);
+        assert!(((dx * dx + dy * dy).sqrt() - 30.0).abs() < 1e-3);
+        // Same direction as the pull, which points at the other body (0.6, -0.8)
+        assert!((dx / 30.0 - acceleration.x / magnitude).abs() < 1e-3 && (dy / 30.0 - acceleration.y / magnitude).abs() < 1e-3);
+        assert!((dx / 30.0 - 0.6).abs() < 1e-2 && (dy / 30.0 + 0.8).abs() < 1e-2);
+        assert_eq!(arrow_tip(from, from, 30.0), from);
+    }
+
+    #[test]
+    fn stop_at_step_pauses_exactly_on_the_target() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.paused = false;
+        state.frame_skip = 4;
+        state.sliders[19].value = 10.0;
+        let mut frames = 0;
+        while !state.paused {
+            assert!(frames < 10, "never paused");
+            state.advance_frame();
+            frames += 1;
+        }
+        assert_eq!(state.steps, 10);
+        assert_eq!(frames, 3);
+        assert_eq!(state.pause_snapshot.as_ref().map(|snapshot| snapshot.steps), Some(10));
+        assert!(!at_step_target(10, 0));
+    }
+
+    #[test]
+    fn interpolated_position_blends_the_last_two_ticks() {
+        let mut particle = Particle::new(10.0, 20.0, -4.0, 1.0);
+        particle.prev_position = Point3 { x: 0.0, y: 40.0, z: 4.0 };
+        let at = |alpha: f32| {
+            let p = particle.interpolated_position(alpha);
+            (p.x, p.y, p.z)
+        };
+        assert_eq!(at(0.0), (0.0, 40.0, 4.0));
+        assert_eq!(at(1.0), (10.0, 20.0, -4.0));
+        assert_eq!(at(0.25), (2.5, 35.0, 2.0));
+    }
+
+    #[test]
+    fn disk_rotates_and_cloud_does_not() {
+        // Net orbital angular momentum as a fraction of what it would be if every body went round the same way
+        let rotation = |scenario: Scenario| {
+            let mut state = SimulationState::new();
+            state.scenario = scenario;
+            state.freeze_seed = true;
+            state.seed = 42;
+            state.reset();
+            let center = state.particles[state.star_index().unwrap()].position;
+            let (net, total) = state.particles.iter().filter(|p| p.mass < state.sliders[6].value).fold((0.0, 0.0), |(net, total), p| {
+                let (dx, dy) = (p.position.x - center.x, p.position.y - center.y);
+                let l = p.mass * (dx * p.velocity.y - dy * p.velocity.x);
+                let most = p.mass * (dx * dx + dy * dy).sqrt() * (p.velocity.x * p.velocity.x + p.velocity.y * p.velocity.y).sqrt();
+                (net + l, total + most)
+            });
+            net / total
+        };
+        assert!(rotation(Scenario::AccretionDisk) > 0.95);
+        assert!(rotation(Scenario::UniformCloud).abs() < 0.15);
+    }
+
+    #[test]
+    fn clicks_on_the_button_strip_never_select_particles() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0)]);
+        let strip = Point2 { x: WINDOW_WIDTH - 5.0, y: 5.0 };
+        assert!(state.buttons.iter().all(|button| !button.contains(strip, state.ui_scale)));
+        let under_strip = state.screen_to_world(strip);
+        state.particles.push(Particle::new(under_strip.x, under_strip.y, 0.0, 1.0));
+        assert_eq!(state.particle_at(strip), Some(1));
+        assert!(state.over_ui(strip));
+
+        state.handle_mouse_click(strip.x, strip.y, false);
+        assert_eq!(state.selected, None);
+        assert!(!state.is_panning);
+
+        // Out in the open a click selects what it lands on
+        let open = state.world_to_screen(&state.particles[0].position);
+        state.handle_mouse_click(open.x, open.y, false);
+        assert_eq!(state.selected, Some(0));
+    }
+
+    #[test]
+    fn two_central_stars_orbit_each_other_and_the_disk_orbits_both() {
+        let center = Point2 { x: 400.0, y: 300.0 };
+        let stars = central_stars(center, 1000.0, 2, 40.0);
+        assert_eq!(stars.len(), 2);
+        let (a, b) = (&stars[0], &stars[1]);
+        assert!((a.position.x + b.position.x - 2.0 * center.x).abs() < 1e-3);
+        assert!((a.position.y + b.position.y - 2.0 * center.y).abs() < 1e-3);
+        let separation = ((a.position.x - b.position.x).powi(2) + (a.position.y - b.position.y).powi(2)).sqrt();
+        assert!((separation - 40.0).abs() < 1e-3);
+        assert!((a.velocity.x + b.velocity.x).abs() < 1e-4 && (a.velocity.y + b.velocity.y).abs() < 1e-4);
+        let relative_speed = ((a.velocity.x - b.velocity.x).powi(2) + (a.velocity.y - b.velocity.y).powi(2)).sqrt();
+        assert!((relative_speed - (G * 1000.0 / 40.0).sqrt()).abs() < 1e-3);
+
+        let mut state = SimulationState::new();
+        state.sliders[20].value = 2.0;
+        state.reset();
+        let (spawn_center, mass) = state.spawn_center();
+        assert!((mass - state.sliders[6].value).abs() < 1e-2);
+        let p = state.spawn_orbiting_particle(&mut StdRng::seed_from_u64(3));
+        let distance = ((p.position.x - spawn_center.x).powi(2) + (p.position.y - spawn_center.y).powi(2)).sqrt();
+        let speed = (p.velocity.x * p.velocity.x + p.velocity.y * p.velocity.y).sqrt();
+        let expected = (G * mass / distance).sqrt() * state.initial_velocity_multiplier;
+        assert!((speed - expected).abs() < 1e-3 * expected, "{} vs {}", speed, expected);
+
+        // Neither star counts as an orbiting body, so losing all but two of
+        // the disk is a collapse
+        assert_eq!(state.star_count, 2);
+        assert_eq!(state.initial_orbiting, state.particles.len() - 2);
+        state.particles.truncate(2 + COLLAPSE_MIN_SURVIVORS - 1);
+        state.step(0.0001);
+        assert!(state.collapsed);
+
+        state.load_binary_preset();
+        assert_eq!((state.star_count, state.initial_orbiting), (2, state.particles.len() - 2));
+    }
+
+
+    #[test]
+    fn particles_expire_exactly_at_their_lifetime() {
+        for recycle in [false, true] {
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+            state.sliders[21].value = 5.0;
+            state.recycle_expired = recycle;
+            let id = state.particles[1].id;
+            for _ in 0..4 {
+                state.tick();
+            }
+            assert_eq!((state.particles.len(), state.particles[1].id, state.particles[1].age), (2, id, 4));
+            state.tick();
+            if recycle {
+                assert_eq!(state.particles.len(), 2);
+                assert!(state.particles[1].id != id && state.particles[1].age == 0);
+            } else {
+                assert_eq!(state.particles.len(), 1);
+            }
+            // The star is never retired
+            assert_eq!(state.particles[0].age, 5);
+
+            // Nor is either of a pair of central stars, though only one is the heaviest
+            let mut state = SimulationState::new();
+            state.sliders[20].value = 2.0;
+            state.sliders[21].value = 5.0;
+            state.recycle_expired = recycle;
+            state.reset();
+            let stars: Vec<u64> = state.particles[..2].iter().map(|p| p.id).collect();
+            for _ in 0..6 {
+                state.tick();
+            }
+            let surviving = state.particles.iter().filter(|p| stars.contains(&p.id)).count();
+            assert_eq!(surviving, 2, "recycle {}", recycle);
+        }
+    }
+
+    #[test]
+    fn drift_starts_at_zero_and_grows_with_a_bad_step() {
+        let energy_drift = |dt: f32, steps: usize| {
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+            state.particles[1].velocity.y = 2.0;
+            state.conserved_at_reset = Conserved::measure(&state.particles);
+            state.sliders[4].value = 0.0;
+            state.sliders[10].value = 0.0;
+            for _ in 0..steps {
+                state.step(dt);
+            }
+            state.conserved_at_reset.drift(&Conserved::measure(&state.particles)).0.abs()
+        };
+        assert!(energy_drift(0.01, 0) < 1e-4);
+        let careful = energy_drift(0.01, 10000);
+        let sloppy = energy_drift(1.0, 100);
+        assert!(sloppy > 0.1, "{}%", sloppy);
+        assert!(sloppy > 10.0 * careful, "{}% vs {}%", sloppy, careful);
+    }
+
+    #[test]
+    fn annuli_bin_particles_by_distance_and_sum_their_mass() {
+        let particles = vec![
+            Particle::new(0.0, 0.0, 0.0, 1000.0),
+            Particle::new(5.0, 0.0, 0.0, 1.0),
+            Particle::new(0.0, -9.0, 0.0, 2.0),
+            Particle::new(6.0, 8.5, 7.0, 4.0),
+            Particle::new(-25.0, 0.0, 0.0, 8.0),
+            Particle::new(100.0, 0.0, 0.0, 16.0),
+        ];
+        let origin = Point3 { x: 0.0, y: 0.0, z: 0.0 };
+        // Height above the plane doesn't count, and the far body falls off the end
+        assert_eq!(annulus_masses(&particles, origin, 10.0, 3, Some(0)), vec![3.0, 4.0, 8.0]);
+        assert_eq!(annulus_masses(&particles, origin, 10.0, 3, None), vec![1003.0, 4.0, 8.0]);
+    }
+
+    #[test]
+    fn lensing_shift_tends_to_the_einstein_radius_and_falls_off() {
+        let einstein = 10.0;
+        assert!((lensing_displacement(einstein, 0.0) - einstein).abs() < 1e-5);
+        // sqrt(15² + 4·10²) = 25, so (25 - 15) / 2
+        assert!((lensing_displacement(einstein, 15.0) - 5.0).abs() < 1e-5);
+        let far = lensing_displacement(einstein, 1000.0);
+        assert!((far - einstein * einstein / 1000.0).abs() < 1e-3);
+        assert!(lensing_displacement(einstein, 50.0) > lensing_displacement(einstein, 100.0));
+        assert_eq!(lensing_displacement(0.0, 40.0), 0.0);
+    }
+
+    #[test]
+    fn leader_follows_identity_not_index() {
+        let mut state = state_with(vec![
+            Particle::new(100.0, 100.0, 0.0, 1.0),
+            Particle::new(200.0, 100.0, 0.0, 100.0),
+            Particle::new(900.0, 600.0, 0.0, 90.0),
+        ]);
+        state.track_leader = true;
+        let (first, second) = (state.particles[1].id, state.particles[2].id);
+        state.step(0.01);
+        assert_eq!((state.leader, state.leader_id), (Some(1), Some(first)));
+
+        // Removing an earlier body shifts the leader's index but it's still the same body
+        state.remove_particle(0);
+        state.step(0.01);
+        assert_eq!((state.leader, state.leader_id), (Some(0), Some(first)));
+        assert!(state.leader_path.iter().all(Option::is_some));
+
+        state.particles[1].mass = 200.0;
+        state.step(0.01);
+        assert_eq!((state.leader, state.leader_id), (Some(1), Some(second)));
+        assert_eq!(state.leader_path.iter().filter(|point| point.is_none()).count(), 1);
+    }
+
+    #[test]
+    fn finer_quality_builds_smoother_circles() {
+        let vertices = |quality: RenderQuality| {
+            let mut builder = graphics::MeshBuilder::new();
+            builder.circle(graphics::DrawMode::fill(), [0.0, 0.0], 50.0, quality.tolerance(), Color::WHITE).unwrap();
+            builder.build().vertices.len()
+        };
+        assert!(vertices(RenderQuality::Low) < vertices(RenderQuality::Medium));
+        assert!(vertices(RenderQuality::Medium) < vertices(RenderQuality::High));
+        assert_eq!(RenderQuality::High.finer(), RenderQuality::High);
+        assert_eq!(RenderQuality::Low.coarser(), RenderQuality::Low);
+        assert_eq!(RenderQuality::Low.finer().finer(), RenderQuality::High);
+    }
+
+    #[test]
+    fn headless_runs_without_a_graphics_context() {
+        let mut state = SimulationState::new();
+        let summary = run_headless(&mut state, 25);
+        assert_eq!(state.steps, 25);
+        assert!(summary.starts_with("25 steps in "), "{}", summary);
+        assert!(summary.contains(&format!("{} particles left", state.particles.len())));
+
+        // Stop At Step cuts the run short and the summary says so
+        state.sliders[19].value = 30.0;
+        let summary = run_headless(&mut state, 100);
+        assert_eq!(state.steps, 30);
+        assert!(summary.starts_with("5 steps in "), "{}", summary);
+    }
+
+    #[test]
+    fn zoom_steps_once_per_wheel_notch() {
+        assert_eq!(zoom_factor(1.0, 1.25, false), 1.25);
+        assert_eq!(zoom_factor(3.0, 1.25, false), 1.25);
+        assert_eq!(zoom_factor(-1.0, 1.25, false), 0.8);
+        assert_eq!(zoom_factor(0.0, 1.25, false), 1.0);
+        // Smooth zooming scales with how far the wheel moved
+        assert!((zoom_factor(2.0, 1.25, true) - 1.5625).abs() < 1e-6);
+        assert!((zoom_factor(0.5, 1.25, true) * zoom_factor(-0.5, 1.25, true) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sweep_gives_one_row_per_value_on_one_seed() {
+        let mut state = SimulationState::new();
+        let rows = run_sweep(&mut state, "softening", &[1.0, 3.0], 5).unwrap();
+        assert_eq!(rows.len(), 2);
+        let fields: Vec<Vec<&str>> = rows.iter().map(|row| row.split(',').collect()).collect();
+        let columns = SWEEP_HEADER.split(',').count();
+        assert!(fields.iter().all(|row| row.len() == columns));
+        assert_eq!((fields[0][0], fields[1][0]), ("Softening", "Softening"));
+        assert_ne!(fields[0][1], fields[1][1]);
+        assert_eq!(fields[0][2], fields[1][2]);
+        assert_eq!(fields[0][3], "5");
+        assert!(!state.freeze_seed);
+        assert!(run_sweep(&mut state, "no such slider", &[1.0], 5).is_err());
+    }
+
+    #[test]
+    fn off_center_merge_keeps_angular_momentum_as_spin() {
+        let mut a = Particle::new(100.0, 100.0, 0.0, 3.0);
+        let mut b = Particle::new(104.0, 100.0, 0.0, 1.0);
+        a.velocity.y = -1.0;
+        b.velocity.y = 3.0;
+        let before = total_angular_momentum_z(&[a.clone(), b.clone()]);
+        a.absorb(&b, RadiusModel::ConstantDensity);
+        // Zero net momentum, so the orbital part vanishes and it's all spin
+        assert_eq!((a.velocity.x, a.velocity.y), (0.0, 0.0));
+        assert!(a.spin > 0.0);
+        let after = total_angular_momentum_z(std::slice::from_ref(&a));
+        assert!((after - before).abs() < 1e-3 * before.abs(), "{} vs {}", after, before);
+    }
+
+    #[test]
+    fn escape_velocity_is_root_two_gm_over_r() {
+        assert!((escape_velocity(1000.0, 20.0) - 10.0).abs() < 1e-5);
+        // Always root two times the circular speed at the same distance
+        let circular = (G * 500.0 / 80.0f32).sqrt();
+        assert!((escape_velocity(500.0, 80.0) - 2.0f32.sqrt() * circular).abs() < 1e-5);
+        assert!(escape_velocity(1000.0, 0.0).is_finite());
+    }
+
+    #[test]
+    fn birth_colors_survive_a_save_and_load() {
+        let mut particles = vec![Particle::new(10.0, 20.0, 0.0, 5.0), Particle::new(30.0, 40.0, 0.0, 6.0)];
+        particles[0].birth_color = Color::from_rgb(255, 128, 0);
+        particles[1].birth_color = Color::from_rgb(12, 34, 250);
+        let loaded = parse_particle_csv(&particles_to_csv(&particles), &Units::SIMULATION).ok().unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (saved, loaded) in particles.iter().zip(&loaded) {
+            assert_eq!(saved.birth_color.to_rgb(), loaded.birth_color.to_rgb());
+        }
+    }
+
+    #[test]
+    fn soft_reset_keeps_placed_masses_and_redraws_the_disk() {
+        let mut state = SimulationState::new();
+        state.add_large_mass(100.0, 100.0);
+        let placed = state.particles.last().unwrap().id;
+        let old: Vec<u64> = state.particles.iter().filter(|p| !p.user_placed).map(|p| p.id).collect();
+        state.soft_reset();
+        let kept: Vec<&Particle> = state.particles.iter().filter(|p| p.user_placed).collect();
+        assert_eq!(kept.len(), 1);
+        assert_eq!((kept[0].id, kept[0].position.x, kept[0].position.y), (placed, 100.0, 100.0));
+        assert!(state.particles.len() > 2);
+        assert!(state.particles.iter().filter(|p| !p.user_placed).all(|p| !old.contains(&p.id)));
+        assert_eq!(state.steps, 0);
+    }
+
+    #[test]
+    fn ghost_comes_from_the_snapshot_and_ignores_stepping() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity.y = 3.0;
+        state.ghost = Some(state.snapshot().ghost());
+        for _ in 0..50 {
+            state.step(0.05);
+        }
+        let ghost = state.ghost.as_ref().unwrap();
+        assert_eq!(ghost.len(), 2);
+        assert_eq!((ghost[1].0.x, ghost[1].0.y), (900.0, 600.0));
+        assert_eq!(ghost[1].1, Particle::new(0.0, 0.0, 0.0, 1.0).radius);
+        assert!(state.particles[1].position.y > 600.0);
+    }
+
+    #[test]
+    fn pair_softening_follows_the_mean_radius() {
+        let expected = (SIZE_SOFTENING_FACTOR * 3.0).powi(2);
+        assert!((pair_softening(2.0, 4.0) - expected).abs() < 1e-5);
+        assert_eq!(pair_softening(2.0, 4.0), pair_softening(4.0, 2.0));
+        assert!((pair_softening(6.0, 6.0) - 4.0 * pair_softening(3.0, 3.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn shown_seed_rebuilds_the_same_disk() {
+        let positions = |state: &SimulationState| state.particles.iter().map(|p| (p.position.x, p.position.y)).collect::<Vec<_>>();
+        let mut original = SimulationState::new();
+        original.reset();
+        let mut replay = SimulationState::new();
+        replay.freeze_seed = true;
+        replay.seed = original.seed;
+        replay.reset();
+        assert_eq!(positions(&replay), positions(&original));
+        replay.reset();
+        assert_eq!(replay.seed, original.seed);
+        assert_eq!(positions(&replay), positions(&original));
+    }
+
+    #[test]
+    fn double_precision_drifts_less_over_a_long_run() {
+        let energy_drift = |double_precision: bool| {
+            let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+            state.particles[1].velocity.y = (G * 1000.0 / 100.0).sqrt();
+            state.double_precision = double_precision;
+            state.sliders[4].value = 0.0;
+            state.sliders[10].value = 0.0;
+            state.conserved_at_reset = Conserved::measure(&state.particles);
+            for _ in 0..20000 {
+                state.step(0.01);
+            }
+            state.conserved_at_reset.drift(&Conserved::measure(&state.particles)).0.abs()
+        };
+        let (single, double) = (energy_drift(false), energy_drift(true));
+        assert!(double * 10.0 < single, "f64 {}% vs f32 {}%", double, single);
+    }
+
+    #[test]
+    fn doubling_the_cross_section_doubles_the_merge_distance() {
+        let at = |distance: f32| vec![Particle::new(0.0, 0.0, 0.0, 1.0), Particle::new(distance, 0.0, 0.0, 1.0)];
+        let contact = 2.0 * Particle::new(0.0, 0.0, 0.0, 1.0).radius;
+        for cross_section in [1.0, 2.0] {
+            let reach = contact * cross_section;
+            assert_eq!(colliding_pairs_brute(&at(reach * 0.99), cross_section, 0.0), vec![(0, 1)]);
+            assert!(colliding_pairs_brute(&at(reach * 1.01), cross_section, 0.0).is_empty());
+            assert_eq!(colliding_pairs_grid(&at(reach * 0.99), cross_section, 0.0), vec![(0, 1)]);
+            assert!(colliding_pairs_grid(&at(reach * 1.01), cross_section, 0.0).is_empty());
+        }
+    }
+
+    #[test]
+    fn hill_radius_scales_with_the_cube_root_of_the_mass_ratio() {
+        // (3 / 3000)^(1/3) is a tenth of the 300-unit orbit
+        assert!((hill_radius(3.0, 1000.0, 300.0) - 30.0).abs() < 1e-3);
+        assert!((hill_radius(8.0, 1000.0, 100.0) - 2.0 * hill_radius(1.0, 1000.0, 100.0)).abs() < 1e-4);
+        assert_eq!(hill_radius(0.0, 1000.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn scripted_mass_arrives_on_its_step() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0)]);
+        state.script = parse_script("# drop a planet in\n3 add 900 600 0 3.2 5\n").unwrap();
+        for _ in 0..2 {
+            state.step(0.01);
+        }
+        assert_eq!(state.particles.len(), 1);
+        state.step(0.01);
+        assert_eq!(state.particles.len(), 2);
+        let added = &state.particles[1];
+        assert_eq!((added.position.x, added.position.y, added.velocity.y, added.mass), (900.0, 600.0, 3.2, 5.0));
+        for _ in 0..5 {
+            state.step(0.01);
+        }
+        assert_eq!(state.particles.len(), 2);
+    }
+
+    #[test]
+    fn streaks_run_from_the_previous_position_to_the_current_one() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity.y = 50.0;
+        state.motion_blur = true;
+        state.paused = true;
+        // Enough steps in one frame for the move to outgrow the dot
+        state.frame_skip = 32;
+        state.advance_frame();
+        let particle = &state.particles[1];
+        let (from, to) = particle.streak(1.0);
+        assert_eq!((from.x, from.y), (900.0, 600.0));
+        assert_eq!((to.x, to.y), (particle.position.x, particle.position.y));
+
+        let (_, streaks) = state.particle_instances(None);
+        assert_eq!(streaks.len(), 1);
+        let (start, end, _, _) = streaks[0];
+        assert_eq!(start, state.world_to_screen(&particle.prev_position));
+        assert_eq!(end, state.world_to_screen(&particle.position));
+    }
+
+    #[test]
+    fn slower_approaches_are_focused_from_further_out() {
+        let escape = 10.0;
+        let fast = focusing_factor(40.0, escape, 1.0);
+        let slow = focusing_factor(5.0, escape, 1.0);
+        assert!((fast - (1.0f32 + 1.0 / 16.0).sqrt()).abs() < 1e-5);
+        assert!((slow - 5.0f32.sqrt()).abs() < 1e-5);
+        assert!(slow > fast && fast > 1.0);
+        assert_eq!(focusing_factor(5.0, escape, 0.0), 1.0);
+        assert_eq!(focusing_factor(0.0, escape, 1.0), FOCUSING_MAX);
+
+        // A slow pair just beyond contact is caught only with focusing on
+        let mut a = Particle::new(0.0, 0.0, 0.0, 50.0);
+        let b = Particle::new(1.2 * 2.0 * a.radius, 0.0, 0.0, 50.0);
+        a.velocity.x = 0.1;
+        assert!(!collides(&a, &b, 1.0, 0.0));
+        assert!(collides(&a, &b, 1.0, 1.0));
+    }
+
+    #[test]
+    fn rotation_turns_points_clockwise_on_screen() {
+        let center = Point2 { x: 100.0, y: 50.0 };
+        let turned = rotate_about(Point2 { x: 110.0, y: 50.0 }, center, PI / 2.0);
+        // With y pointing down, a quarter turn takes +x to +y
+        assert!((turned.x - 100.0).abs() < 1e-4 && (turned.y - 60.0).abs() < 1e-4);
+        let back = rotate_about(turned, center, -PI / 2.0);
+        assert!((back.x - 110.0).abs() < 1e-4 && (back.y - 50.0).abs() < 1e-4);
+        assert_eq!(rotate_about(center, center, 1.0), center);
+    }
+
+    #[test]
+    fn phase_labels_follow_the_metrics() {
+        assert_eq!(classify_phase(0.0, 0.1, 0.2), Phase::Relaxed);
+        assert_eq!(classify_phase(2.0, 0.1, 0.2), Phase::Accreting);
+        // Fast merging counts as accretion once one body holds most of the mass
+        assert_eq!(classify_phase(8.0, 0.1, 0.9), Phase::Accreting);
+        assert_eq!(classify_phase(8.0, 0.1, 0.2), Phase::Collapsing);
+        assert_eq!(classify_phase(0.0, -25.0, 0.9), Phase::Collapsing);
+    }
+
+    #[test]
+    fn clearing_visuals_leaves_the_physics_alone() {
+        let mut state = state_with(vec![Particle::new(800.0, 600.0, 0.0, 1000.0), Particle::new(900.0, 600.0, 0.0, 1.0)]);
+        state.particles[1].velocity.y = 3.0;
+        state.particles[1].trail = Some(VecDeque::new());
+        state.track_leader = true;
+        for _ in 0..10 {
+            state.step(0.01);
+        }
+        state.merge_effects.push(MergeEffect { position: state.particles[0].position, age: 0 });
+        let before: Vec<(f32, f32, f32, f32)> =
+            state.particles.iter().map(|p| (p.position.x, p.position.y, p.velocity.x, p.velocity.y)).collect();
+
+        state.clear_visuals();
+        assert_eq!(state.particles[1].trail.as_ref().map(VecDeque::len), Some(0));
+        assert!(state.merge_effects.is_empty() && state.leader_path.is_empty());
+        let after: Vec<(f32, f32, f32, f32)> =
+            state.particles.iter().map(|p| (p.position.x, p.position.y, p.velocity.x, p.velocity.y)).collect();
+        assert_eq!(after, before);
+        assert_eq!(state.steps, 10);
+
+        state.undo_clear_visuals();
+        assert_eq!(state.particles[1].trail.as_ref().map(VecDeque::len), Some(10));
+        assert_eq!((state.merge_effects.len(), state.leader_path.len()), (1, 10));
+    }
+
+    #[test]
+    fn gravity_gun_pulls_toward_the_cursor() {
+        let cursor = Point2 { x: 400.0, y: 300.0 };
+        let mut state = state_with(vec![Particle::new(0.0, 0.0, 0.0, 1.0)]);
+        let target = state.screen_to_world(cursor);
+        state.particles[0].position = Point3 { x: target.x - 30.0, y: target.y + 40.0, z: 0.0 };
+        state.sliders[24].value = 500.0;
+        state.gun = Some((cursor, 1.0));
+        state.step(0.01);
+        let velocity = state.particles[0].velocity;
+        let speed = (velocity.x * velocity.x + velocity.y * velocity.y).sqrt();
+        // Toward the cursor, which is along (0.6, -0.8) from the particle
+        assert!(speed > 0.0);
+        assert!((velocity.x / speed - 0.6).abs() < 1e-3 && (velocity.y / speed + 0.8).abs() < 1e-3);
+
+        // Reversed, it pushes away
+        state.particles[0].velocity = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+        state.gun = Some((cursor, -1.0));
+        state.step(0.01);
+        assert!(state.particles[0].velocity.x < 0.0 && state.particles[0].velocity.y > 0.0);
+    }
+
+    #[test]
+    fn velocity_grid_averages_each_cell_by_mass() {
+        let moving = |x: f32, y: f32, mass: f32, vx: f32, vy: f32| {
+            let mut particle = Particle::new(x, y, 0.0, mass);
+            particle.velocity = Vector3 { x: vx, y: vy, z: 0.0 };
+            particle
+        };
+        let particles = vec![
+            moving(5.0, 5.0, 1000.0, 9.0, 9.0),
+            moving(2.0, 3.0, 1.0, 4.0, 0.0),
+            moving(8.0, 1.0, 3.0, 0.0, 4.0),
+            moving(-5.0, 15.0, 2.0, -1.0, 2.0),
+        ];
+        let mut grid = mean_velocity_grid(&particles, 10.0, Some(0));
+        grid.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+        assert_eq!(grid.len(), 2);
+        let (center, velocity) = grid[1];
+        assert_eq!((center.x, center.y), (5.0, 5.0));
+        assert_eq!((velocity.x, velocity.y), (1.0, 3.0));
+        let (center, velocity) = grid[0];
+        assert_eq!((center.x, center.y), (-5.0, 15.0));
+        assert_eq!((velocity.x, velocity.y), (-1.0, 2.0));
+    }
+
+    #[test]
+    fn ephemeris_import_scales_the_sun_and_earth() {
+        let csv = "x_au,y_au,vx_kms,vy_kms,mass_msun\n0,0,0,0,1\n1,0,0,29.78,0.000003\n";
+        let units = Units::astronomical(0.01, 0.01);
+        let particles = parse_particle_csv(csv, &units).ok().unwrap();
+        let (sun, earth) = (&particles[0], &particles[1]);
+        assert_eq!((sun.position.x, sun.position.y), (WINDOW_CENTER.x, WINDOW_CENTER.y));
+        // G = 4π² AU³/(M☉ yr²) rescaled to G = 1 at 0.01 AU and 0.01 yr per unit
+        assert!((sun.mass - 400.0 * PI * PI).abs() < 0.1, "{}", sun.mass);
+        assert!((earth.position.x - WINDOW_CENTER.x - 100.0).abs() < 1e-3);
+        assert!((earth.velocity.y - 6.282).abs() < 1e-2, "{}", earth.velocity.y);
+        // Which is the circular speed there, as it should be for Earth
+        let circular = (G * sun.mass / 100.0).sqrt();
+        assert!((earth.velocity.y - circular).abs() < 0.01 * circular);
+    }
+
+    #[test]
+    fn separated_clumps_get_their_own_cluster_labels() {
+        let mut particles = vec![Particle::new(800.0, 600.0, 0.0, 1000.0)];
+        // A chain linked only through neighbours, a tight clump, and a pair too small to count
+        particles.extend((0..4).map(|i| Particle::new(100.0 + 15.0 * i as f32, 100.0, 0.0, 1.0)));
+        particles.extend((0..3).map(|i| Particle::new(400.0, 300.0 + 5.0 * i as f32, 0.0, 2.0)));
+        particles.extend((0..2).map(|i| Particle::new(100.0, 500.0 + 5.0 * i as f32, 0.0, 1.0)));
+        let labels = friends_of_friends(&particles, CLUSTER_LINKING_LENGTH, Some(0));
+        let expected = [None, Some(0), Some(0), Some(0), Some(0), Some(1), Some(1), Some(1), None, None];
+        assert_eq!(labels, expected);
+
+        let mut state = state_with(particles);
+        state.scan_clusters();
+        assert_eq!(state.cluster_summary, (2, 6.0));
+    }
+
+    #[test]
+    fn recycling_and_the_binary_preset_replay_from_the_seed() {
+        let positions = |state: &SimulationState| state.particles.iter().map(|p| (p.position.x, p.position.y)).collect::<Vec<_>>();
+        let recycled_run = || {
+            let mut state = SimulationState::new();
+            state.freeze_seed = true;
+            state.seed = 7;
+            state.sliders[21].value = 5.0;
+            state.recycle_expired = true;
+            state.reset();
+            for _ in 0..12 {
+                state.tick();
+            }
+            positions(&state)
+        };
+        assert_eq!(recycled_run(), recycled_run());
+
+        let binary_disk = || {
+            let mut state = SimulationState::new();
+            state.freeze_seed = true;
+            state.seed = 7;
+            state.load_binary_preset();
+            positions(&state)
+        };
+        assert_eq!(binary_disk(), binary_disk());
+    }
+}